@@ -0,0 +1,693 @@
+use crate::jsonl::{is_jsonl, JsonlBatches, JsonlWriter};
+use anyhow::{anyhow, Result};
+use arrow::ipc::{
+    reader::{FileReader, StreamReader},
+    writer::{FileWriter, IpcWriteOptions, StreamWriter},
+    CompressionType,
+};
+use arrow::{
+    compute::{cast, interleave},
+    datatypes::{DataType, SchemaRef},
+};
+use arrow_array::{cast::AsArray, ArrayRef, RecordBatch, RecordBatchReader, StringArray};
+use log::info;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::arrow_writer::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use speedy::{Readable, Writable};
+use std::fs::{create_dir_all, metadata, File};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Magic bytes of arrow ipc file format, stream format has no magic
+const ARROW_FILE_MAGIC: &[u8; 6] = b"ARROW1";
+
+#[derive(clap::ValueEnum, Readable, Writable, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NullPolicy {
+    // rows with null texts are dropped from output files
+    Skip,
+    // null texts are processed as empty texts
+    Empty,
+    // rows with null texts are kept in output files and never deduplicated
+    Keep,
+}
+
+// Separator of normalized texts of hash columns, texts are joined after normalization,
+// so it is never removed
+pub const HASH_COLUMNS_SEPARATOR: &str = "\u{1f}";
+
+// How texts of several columns make a document
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ColumnsCombination {
+    // texts are joined by the separator
+    Join { separator: String },
+    // texts are minhashed separately, every column gets a share of permutations by its weight,
+    // texts are joined by new lines elsewhere
+    Weighted { weights: Vec<u32> },
+}
+
+// Columns with texts, string, large string, dictionary and binary columns are supported,
+// binary values are decoded as utf-8
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct TextColumns {
+    names: Vec<String>,
+    combination: ColumnsCombination,
+    // columns of exact content hash, text columns if empty
+    hash_names: Vec<String>,
+    null_policy: NullPolicy,
+    // invalid utf-8 is replaced instead of failing
+    lossy: bool,
+}
+
+impl TextColumns {
+    pub fn new(
+        names: Vec<String>,
+        combination: ColumnsCombination,
+        hash_names: Vec<String>,
+        null_policy: NullPolicy,
+        lossy: bool,
+    ) -> Self {
+        assert!(!names.is_empty());
+        if let ColumnsCombination::Weighted { weights } = &combination {
+            assert_eq!(weights.len(), names.len());
+        }
+        Self {
+            names,
+            combination,
+            hash_names,
+            null_policy,
+            lossy,
+        }
+    }
+
+    // Single text column, texts are read by the name of the first column otherwise
+    pub fn single(name: String, null_policy: NullPolicy, lossy: bool) -> Self {
+        Self::new(
+            vec![name],
+            ColumnsCombination::Join {
+                separator: "\n".to_string(),
+            },
+            Vec::new(),
+            null_policy,
+            lossy,
+        )
+    }
+
+    pub fn names(&self) -> &Vec<String> {
+        &self.names
+    }
+
+    // Text and hash columns without repetitions
+    pub fn all_names(&self) -> Vec<String> {
+        let mut result = self.names.clone();
+        for name in &self.hash_names {
+            if !result.contains(name) {
+                result.push(name.clone());
+            }
+        }
+        result
+    }
+
+    pub fn weights(&self) -> Option<&Vec<u32>> {
+        match &self.combination {
+            ColumnsCombination::Join { .. } => None,
+            ColumnsCombination::Weighted { weights } => Some(weights),
+        }
+    }
+
+    // Lsh rows are comparable if they are built from the same columns combined in the same way,
+    // source files of reference rows are read by these columns in verification and diff
+    pub fn is_compatible(&self, other: &TextColumns) -> bool {
+        self.names == other.names
+            && self.combination == other.combination
+            && self.hash_names == other.hash_names
+    }
+
+    // Rows which are dropped from output files
+    pub fn is_skipped(&self, text: &Option<String>) -> bool {
+        text.is_none() && self.null_policy == NullPolicy::Skip
+    }
+
+    fn decode(&self, value: &[u8], column: &str, path: &str) -> Result<String> {
+        if self.lossy {
+            return Ok(String::from_utf8_lossy(value).into_owned());
+        }
+        Ok(std::str::from_utf8(value)
+            .map_err(|e| {
+                anyhow!(
+                    "Cannot decode text in column {} in file {}, lossy decoding is disabled: {}",
+                    column,
+                    path,
+                    e
+                )
+            })?
+            .to_string())
+    }
+
+    fn read_column(
+        &self,
+        record_batch: &RecordBatch,
+        name: &str,
+        path: &str,
+    ) -> Result<Vec<Option<String>>> {
+        let column = record_batch.column_by_name(name).ok_or(anyhow!(
+            "Cannot find column {} in file {}",
+            name,
+            path
+        ))?;
+
+        let value_type = match column.data_type() {
+            DataType::Dictionary(_, value_type) => value_type.as_ref(),
+            x => x,
+        };
+        let texts = match value_type {
+            DataType::Utf8 | DataType::LargeUtf8 => cast(column, &DataType::LargeUtf8)?
+                .as_string::<i64>()
+                .iter()
+                .map(|x| x.map(|x| x.to_string()))
+                .collect(),
+            DataType::Binary | DataType::LargeBinary => cast(column, &DataType::LargeBinary)?
+                .as_binary::<i64>()
+                .iter()
+                .map(|x| x.map(|x| self.decode(x, name, path)).transpose())
+                .collect::<Result<_>>()?,
+            x => {
+                return Err(anyhow!(
+                    "Column {} in file {} has unsupported type {}",
+                    name,
+                    path,
+                    x
+                ))
+            }
+        };
+        Ok(texts)
+    }
+
+    // Texts of every row by columns, row is null only if all its columns are null,
+    // null texts of other columns are empty
+    fn read_columns(
+        &self,
+        record_batch: &RecordBatch,
+        names: &[String],
+        path: &str,
+    ) -> Result<Vec<Option<Vec<String>>>> {
+        let columns = names
+            .iter()
+            .map(|x| self.read_column(record_batch, x, path))
+            .collect::<Result<Vec<_>>>()?;
+        let result = (0..record_batch.num_rows())
+            .map(|i| {
+                if self.null_policy != NullPolicy::Empty && columns.iter().all(|x| x[i].is_none()) {
+                    return None;
+                }
+                Some(
+                    columns
+                        .iter()
+                        .map(|x| x[i].clone().unwrap_or_default())
+                        .collect(),
+                )
+            })
+            .collect();
+        Ok(result)
+    }
+
+    // Texts of text columns by columns
+    pub fn read_parts(
+        &self,
+        record_batch: &RecordBatch,
+        path: &str,
+    ) -> Result<Vec<Option<Vec<String>>>> {
+        self.read_columns(record_batch, &self.names, path)
+    }
+
+    // Texts of hash columns by columns, none if content hash is computed over texts
+    pub fn read_hash_parts(
+        &self,
+        record_batch: &RecordBatch,
+        path: &str,
+    ) -> Result<Option<Vec<Option<Vec<String>>>>> {
+        if self.hash_names.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.read_columns(
+            record_batch,
+            &self.hash_names,
+            path,
+        )?))
+    }
+
+    pub fn read_texts(
+        &self,
+        record_batch: &RecordBatch,
+        path: &str,
+    ) -> Result<Vec<Option<String>>> {
+        if self.names.len() == 1 {
+            let texts = self.read_column(record_batch, &self.names[0], path)?;
+            if self.null_policy == NullPolicy::Empty {
+                return Ok(texts
+                    .into_iter()
+                    .map(|x| Some(x.unwrap_or_default()))
+                    .collect());
+            }
+            return Ok(texts);
+        }
+
+        let separator = match &self.combination {
+            ColumnsCombination::Join { separator } => separator.as_str(),
+            ColumnsCombination::Weighted { .. } => "\n",
+        };
+        Ok(self
+            .read_parts(record_batch, path)?
+            .into_iter()
+            .map(|x| x.map(|x| x.join(separator)))
+            .collect())
+    }
+
+    // Column with texts of changed rows replaced, other rows keep their original values,
+    // so untouched binary texts and null texts are written as they are
+    pub fn replace_texts(column: &ArrayRef, changed: &[(usize, String)]) -> Result<ArrayRef> {
+        if changed.is_empty() {
+            return Ok(column.clone());
+        }
+        let texts = StringArray::from_iter_values(changed.iter().map(|x| &x.1));
+        let replacement = cast(&texts, column.data_type())?;
+        let mut indices: Vec<(usize, usize)> = (0..column.len()).map(|i| (0, i)).collect();
+        for (i, (row, _)) in changed.iter().enumerate() {
+            indices[*row] = (1, i);
+        }
+        Ok(interleave(
+            &[column.as_ref(), replacement.as_ref()],
+            &indices,
+        )?)
+    }
+}
+
+pub fn is_ipc(path: &str) -> bool {
+    [".arrow", ".arrows", ".feather", ".ipc"]
+        .iter()
+        .any(|x| path.ends_with(x))
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    // format of the input file
+    Auto,
+    // zstd compressed parquet
+    Parquet,
+    // zstd compressed arrow ipc stream
+    Arrow,
+}
+
+// Output file of an input file and its rows, entries of the manifest
+struct ManifestEntry {
+    input_path: String,
+    output_path: String,
+    input_rows: u64,
+    output_rows: u64,
+}
+
+// Where and in which format output files are written, jsonl files are always written
+// as jsonl with the same compression
+#[derive(Clone)]
+pub struct Output {
+    folder: String,
+    format: OutputFormat,
+    // relative paths of input files in this folder are kept in output folder
+    mirrored_folder: Option<String>,
+    manifest: Arc<Mutex<Vec<ManifestEntry>>>,
+}
+
+impl Output {
+    pub fn new(folder: &str, format: OutputFormat, mirrored_folder: Option<&str>) -> Self {
+        Self {
+            folder: folder.to_string(),
+            format,
+            mirrored_folder: mirrored_folder.map(|x| x.to_string()),
+            manifest: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn mirrored_file_path(&self, input_path: &str, mirrored_folder: &str) -> Result<String> {
+        let relative = Path::new(input_path)
+            .strip_prefix(mirrored_folder)
+            .map_err(|_| anyhow!("{} is not in {}", input_path, mirrored_folder))?;
+        // extension is changed only if the format is changed
+        let relative = match self.format {
+            _ if is_jsonl(input_path) => relative.to_path_buf(),
+            OutputFormat::Parquet if is_ipc(input_path) => relative.with_extension("parquet"),
+            OutputFormat::Arrow if !is_ipc(input_path) => relative.with_extension("arrows"),
+            _ => relative.to_path_buf(),
+        };
+        Ok(Path::new(&self.folder)
+            .join(relative)
+            .to_str()
+            .unwrap()
+            .to_string())
+    }
+
+    pub fn file_path(&self, input_path: &str) -> Result<String> {
+        if let Some(mirrored_folder) = &self.mirrored_folder {
+            return self.mirrored_file_path(input_path, mirrored_folder);
+        }
+
+        let extension = if is_jsonl(input_path) {
+            if input_path.ends_with(".gz") {
+                "jsonl.gz"
+            } else if input_path.ends_with(".zst") {
+                "jsonl.zst"
+            } else {
+                "jsonl"
+            }
+        } else {
+            match self.format {
+                OutputFormat::Auto if is_ipc(input_path) => "arrows",
+                OutputFormat::Auto | OutputFormat::Parquet => "parquet.zst",
+                OutputFormat::Arrow => "arrows",
+            }
+        };
+        Ok(format!(
+            "{}/{:x}.{}",
+            self.folder,
+            md5::compute(input_path),
+            extension
+        ))
+    }
+
+    pub fn add_manifest_entry(
+        &self,
+        input_path: &str,
+        output_path: &str,
+        input_rows: u64,
+        output_rows: u64,
+    ) {
+        self.manifest.lock().unwrap().push(ManifestEntry {
+            input_path: input_path.to_string(),
+            output_path: output_path.to_string(),
+            input_rows,
+            output_rows,
+        });
+    }
+
+    // Output file, rows and bytes before and after of every input file
+    pub fn manifest(&self) -> Result<Vec<serde_json::Value>> {
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.sort_by(|a, b| a.input_path.cmp(&b.input_path));
+        manifest
+            .iter()
+            .map(|x| {
+                Ok(serde_json::json!({
+                    "input_path": x.input_path,
+                    "output_path": x.output_path,
+                    "input_rows": x.input_rows,
+                    "output_rows": x.output_rows,
+                    "input_bytes": metadata(&x.input_path)?.len(),
+                    "output_bytes": metadata(&x.output_path)?.len(),
+                }))
+            })
+            .collect()
+    }
+
+    pub fn write_manifest(&self) -> Result<()> {
+        let path = format!("{}/manifest.json", self.folder);
+        let writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(writer, &serde_json::json!({ "files": self.manifest()? }))?;
+        info!("Manifest is written to {}", path);
+        Ok(())
+    }
+
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+}
+
+type RecordBatches = Box<dyn Iterator<Item = Result<RecordBatch>> + Send>;
+
+fn open_ipc_batches(path: &str) -> Result<(SchemaRef, RecordBatches)> {
+    let mut magic = [0u8; 6];
+    let is_file_format =
+        File::open(path)?.read_exact(&mut magic).is_ok() && &magic == ARROW_FILE_MAGIC;
+    if is_file_format {
+        let batches = FileReader::try_new(File::open(path)?, None)?;
+        return Ok((
+            batches.schema(),
+            Box::new(batches.map(|x| x.map_err(anyhow::Error::from))),
+        ));
+    }
+    let batches = StreamReader::try_new(BufReader::new(File::open(path)?), None)?;
+    Ok((
+        batches.schema(),
+        Box::new(batches.map(|x| x.map_err(anyhow::Error::from))),
+    ))
+}
+
+// Record batches of parquet, arrow ipc or jsonl file, for jsonl the text is taken by the json path
+// given as column and the whole line is kept in an additional column
+fn open_batches(path: &str, column: &TextColumns) -> Result<(SchemaRef, RecordBatches)> {
+    if is_jsonl(path) {
+        let batches = JsonlBatches::try_new(path, &column.all_names())?;
+        return Ok((batches.schema(), Box::new(batches)));
+    }
+    if is_ipc(path) {
+        return open_ipc_batches(path);
+    }
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let batches = builder.build()?;
+    Ok((
+        batches.schema(),
+        Box::new(batches.map(|x| x.map_err(anyhow::Error::from))),
+    ))
+}
+
+pub struct TextReader {
+    path: String,
+    column: TextColumns,
+    batches: RecordBatches,
+    texts: Vec<Option<String>>,
+}
+
+impl TextReader {
+    pub fn try_new(path: &str, column: &TextColumns) -> Result<Self> {
+        Ok(Self {
+            path: path.to_string(),
+            column: column.clone(),
+            batches: open_batches(path, column)?.1,
+            texts: Vec::new(),
+        })
+    }
+
+    fn read_texts(&mut self) -> Result<()> {
+        while self.texts.is_empty() {
+            let record_batch = self.batches.next();
+            if record_batch.is_none() {
+                break;
+            }
+            let record_batch = record_batch.unwrap()?;
+
+            self.texts = self.column.read_texts(&record_batch, &self.path)?;
+            self.texts.reverse();
+        }
+
+        Ok(())
+    }
+
+    pub fn has_data_left(&mut self) -> Result<bool> {
+        if self.texts.is_empty() {
+            self.read_texts()?;
+        }
+        Ok(!self.texts.is_empty())
+    }
+
+    pub fn next(&mut self) -> Result<Option<String>> {
+        if self.texts.is_empty() {
+            self.read_texts()?;
+        }
+        Ok(self.texts.pop().unwrap())
+    }
+}
+
+// Reads whole record batches with all columns, texts of the column are returned
+// alongside, so rows can be filtered without losing other columns
+pub struct BatchReader {
+    path: String,
+    column: TextColumns,
+    schema: SchemaRef,
+    batches: RecordBatches,
+    num_rows: u64,
+}
+
+impl BatchReader {
+    pub fn try_new(path: &str, column: &TextColumns) -> Result<Self> {
+        let (schema, batches) = open_batches(path, column)?;
+        Ok(Self {
+            path: path.to_string(),
+            column: column.clone(),
+            schema,
+            batches,
+            num_rows: 0,
+        })
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    pub fn next(&mut self) -> Result<Option<(RecordBatch, Vec<Option<String>>)>> {
+        let record_batch = self.batches.next();
+        if record_batch.is_none() {
+            return Ok(None);
+        }
+        let record_batch = record_batch.unwrap()?;
+        let texts = self.column.read_texts(&record_batch, &self.path)?;
+        self.num_rows += record_batch.num_rows() as u64;
+        Ok(Some((record_batch, texts)))
+    }
+
+    // Rows which are read so far
+    pub fn num_rows(&self) -> u64 {
+        self.num_rows
+    }
+}
+
+enum BatchWriterInner {
+    Parquet(Box<ArrowWriter<BufWriter<File>>>),
+    IpcStream(StreamWriter<BufWriter<File>>),
+    IpcFile(FileWriter<BufWriter<File>>),
+    Jsonl(JsonlWriter),
+}
+
+// Writes parquet, arrow ipc stream (.arrows), arrow ipc file or jsonl file depending on the path,
+// folders of the path are created
+pub struct BatchWriter {
+    inner: BatchWriterInner,
+    num_rows: u64,
+}
+
+impl BatchWriter {
+    fn create_inner(path: &str, schema: SchemaRef) -> Result<BatchWriterInner> {
+        if is_jsonl(path) {
+            return Ok(BatchWriterInner::Jsonl(JsonlWriter::new(path, schema)?));
+        }
+        if is_ipc(path) {
+            let options =
+                IpcWriteOptions::default().try_with_compression(Some(CompressionType::ZSTD))?;
+            let file = BufWriter::new(File::create(path)?);
+            if path.ends_with(".arrows") {
+                let writer = StreamWriter::try_new_with_options(file, &schema, options)?;
+                return Ok(BatchWriterInner::IpcStream(writer));
+            }
+            let writer = FileWriter::try_new_with_options(file, &schema, options)?;
+            return Ok(BatchWriterInner::IpcFile(writer));
+        }
+
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::try_new(5)?))
+            .build();
+
+        let writer =
+            ArrowWriter::try_new(BufWriter::new(File::create(path)?), schema, Some(props))?;
+        Ok(BatchWriterInner::Parquet(Box::new(writer)))
+    }
+
+    pub fn new(path: &str, schema: SchemaRef) -> Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            create_dir_all(parent)?;
+        }
+        Ok(Self {
+            inner: Self::create_inner(path, schema)?,
+            num_rows: 0,
+        })
+    }
+
+    pub fn write(&mut self, record_batch: &RecordBatch) -> Result<()> {
+        if record_batch.num_rows() == 0 {
+            return Ok(());
+        }
+        match &mut self.inner {
+            BatchWriterInner::Parquet(writer) => writer.write(record_batch)?,
+            BatchWriterInner::IpcStream(writer) => writer.write(record_batch)?,
+            BatchWriterInner::IpcFile(writer) => writer.write(record_batch)?,
+            BatchWriterInner::Jsonl(writer) => writer.write(record_batch)?,
+        }
+        self.num_rows += record_batch.num_rows() as u64;
+        Ok(())
+    }
+
+    // Rows which are written so far
+    pub fn num_rows(&self) -> u64 {
+        self.num_rows
+    }
+
+    pub fn close(self) -> Result<()> {
+        match self.inner {
+            BatchWriterInner::Parquet(writer) => {
+                writer.close()?;
+            }
+            BatchWriterInner::IpcStream(mut writer) => writer.finish()?,
+            BatchWriterInner::IpcFile(mut writer) => writer.finish()?,
+            BatchWriterInner::Jsonl(writer) => writer.close()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::Int32Type;
+    use arrow_array::{Array, BinaryArray, DictionaryArray};
+
+    #[test]
+    fn unchanged_column_is_kept() {
+        let column: ArrayRef = Arc::new(BinaryArray::from(vec![
+            Some(b"\xff\xfe".as_ref()),
+            None,
+            Some(b"text".as_ref()),
+        ]));
+        let result = TextColumns::replace_texts(&column, &[]).unwrap();
+        assert_eq!(&result, &column);
+    }
+
+    #[test]
+    fn only_changed_rows_are_replaced() {
+        let column: ArrayRef = Arc::new(BinaryArray::from(vec![
+            Some(b"\xff\xfe".as_ref()),
+            None,
+            Some(b"long text".as_ref()),
+        ]));
+        let result = TextColumns::replace_texts(&column, &[(2, "text".to_string())]).unwrap();
+        let result = result.as_binary::<i32>();
+        assert_eq!(result.value(0), b"\xff\xfe");
+        assert!(result.is_null(1));
+        assert_eq!(result.value(2), b"text");
+    }
+
+    #[test]
+    fn dictionary_column_keeps_its_type() {
+        let column: ArrayRef = Arc::new(
+            vec![Some("a b"), None, Some("a b")]
+                .into_iter()
+                .collect::<DictionaryArray<Int32Type>>(),
+        );
+        let result = TextColumns::replace_texts(&column, &[(0, "a".to_string())]).unwrap();
+        assert_eq!(result.data_type(), column.data_type());
+        let result = cast(&result, &DataType::Utf8).unwrap();
+        let result = result.as_string::<i32>();
+        assert_eq!(result.value(0), "a");
+        assert!(result.is_null(1));
+        assert_eq!(result.value(2), "a b");
+    }
+
+    #[test]
+    fn null_policy_empty_reads_nulls_as_empty_texts() {
+        let column: ArrayRef = Arc::new(StringArray::from(vec![Some("text"), None]));
+        let record_batch = RecordBatch::try_from_iter([("content", column)]).unwrap();
+        let columns = TextColumns::single("content".to_string(), NullPolicy::Empty, false);
+        let texts = columns.read_texts(&record_batch, "test").unwrap();
+        assert_eq!(texts, vec![Some("text".to_string()), Some(String::new())]);
+    }
+}
@@ -0,0 +1,469 @@
+use crate::{
+    batch_io::{BatchReader, BatchWriter, NullPolicy, Output, TextColumns},
+    context::{Context, DocId},
+    jsonl::{is_jsonl, json_pointer, open_lines},
+    lsh::{create_lsh_buckets, LshBucketRowsFilesMerger, LshBucketsParams},
+    minhash::{hash_text, MinHash},
+    operations,
+    verification::{Signature, Verification},
+};
+use anyhow::{anyhow, Result};
+use arrow::{
+    compute::{cast, filter_record_batch},
+    datatypes::{DataType, Field, Schema},
+};
+use arrow_array::{Array, BooleanArray, Float64Array, RecordBatch, StringArray, UInt32Array};
+use indicatif::ProgressBar;
+use log::{debug, error, info};
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use threadpool::ThreadPool;
+
+// Document of evaluation set, id is taken from the id column or is path and row index
+pub struct BenchmarkDocument {
+    id: String,
+    text: String,
+}
+
+// Minhash of a benchmark document or of its window
+struct Probe {
+    document: usize,
+    minhash: MinHash,
+}
+
+// Contaminated corpus document and the most similar benchmark document
+pub struct Match {
+    doc_id: DocId,
+    benchmark_id: String,
+    similarity: f64,
+}
+
+// Texts and ids are found by json paths as in other jsonl readers, lines without
+// a string text are skipped as null texts of parquet files
+fn read_jsonl_benchmark(
+    path: &str,
+    column: &str,
+    id_column: Option<&str>,
+) -> Result<Vec<BenchmarkDocument>> {
+    let mut result = Vec::new();
+    let column = json_pointer(column);
+    let id_column = id_column.map(json_pointer);
+    for (row_index, line) in open_lines(path)?.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("Cannot parse line {} of {}: {}", row_index + 1, path, e))?;
+        let text = match value.pointer(&column).and_then(|x| x.as_str()) {
+            Some(text) => text,
+            None => continue,
+        };
+        let id = match id_column.as_ref().and_then(|x| value.pointer(x)) {
+            Some(serde_json::Value::String(x)) => x.clone(),
+            Some(serde_json::Value::Null) | None => format!("{}:{}", path, row_index),
+            Some(x) => x.to_string(),
+        };
+        result.push(BenchmarkDocument {
+            id,
+            text: text.to_string(),
+        });
+    }
+    Ok(result)
+}
+
+fn read_parquet_benchmark(
+    path: &str,
+    column: &str,
+    id_column: Option<&str>,
+) -> Result<Vec<BenchmarkDocument>> {
+    let mut result = Vec::new();
+    let column = TextColumns::single(column.to_string(), NullPolicy::Skip, false);
+    let mut reader = BatchReader::try_new(path, &column)?;
+    let mut row_index = 0usize;
+    while let Some((record_batch, texts)) = reader.next()? {
+        let ids = match id_column {
+            Some(id_column) => {
+                let ids = record_batch.column_by_name(id_column).ok_or(anyhow!(
+                    "Cannot find column {} in file {}",
+                    id_column,
+                    path
+                ))?;
+                Some(cast(ids, &DataType::Utf8)?)
+            }
+            None => None,
+        };
+        let ids = ids
+            .as_ref()
+            .map(|x| x.as_any().downcast_ref::<StringArray>().unwrap());
+        for (i, text) in texts.into_iter().enumerate() {
+            row_index += 1;
+            let text = match text {
+                Some(text) => text,
+                None => continue,
+            };
+            let id = match ids.filter(|x| x.is_valid(i)) {
+                Some(ids) => ids.value(i).to_string(),
+                None => format!("{}:{}", path, row_index - 1),
+            };
+            result.push(BenchmarkDocument { id, text });
+        }
+    }
+    Ok(result)
+}
+
+pub fn read_benchmark(
+    paths: &[String],
+    column: &str,
+    id_column: Option<&str>,
+) -> Result<Vec<BenchmarkDocument>> {
+    let mut result = Vec::new();
+    for path in paths {
+        let documents = if is_jsonl(path) {
+            read_jsonl_benchmark(path, column, id_column)?
+        } else {
+            read_parquet_benchmark(path, column, id_column)?
+        };
+        info!("Read benchmark {}, documents: {}", path, documents.len());
+        result.extend(documents);
+    }
+    Ok(result)
+}
+
+// Windows of the given number of words, the whole text if it is not longer than the window
+fn text_windows(text: &str, params: &LshBucketsParams, window: Option<usize>) -> Vec<String> {
+    let window = match window {
+        Some(window) => window,
+        None => return vec![text.to_string()],
+    };
+    let words = params.minhash_params().tokenizer().words(text);
+    if words.len() <= window {
+        return vec![words.join(" ")];
+    }
+    let stride = cmp::max(window / 2, 1);
+    let mut result = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = cmp::min(start + window, words.len());
+        result.push(words[start..end].join(" "));
+        if end == words.len() {
+            break;
+        }
+        start += stride;
+    }
+    result
+}
+
+fn create_probes(
+    documents: &[BenchmarkDocument],
+    params: &LshBucketsParams,
+    window: Option<usize>,
+) -> Vec<Probe> {
+    let mut result = Vec::new();
+    let mut num_skipped = 0u64;
+    for (document, x) in documents.iter().enumerate() {
+        let text = params.normalization().apply(&x.text);
+        for window_text in text_windows(&text, params, window) {
+            match hash_text(&window_text, params.minhash_params()) {
+                Some(minhash) => result.push(Probe { document, minhash }),
+                None => num_skipped += 1,
+            }
+        }
+    }
+    info!(
+        "Created benchmark probes: {}, skipped short windows: {}",
+        result.len(),
+        num_skipped
+    );
+    result
+}
+
+// Corpus documents which collide with any probe in any lsh bucket
+fn find_candidates(
+    context: &Context,
+    params: &LshBucketsParams,
+    probes: &[Probe],
+) -> Result<HashMap<DocId, HashSet<usize>>> {
+    let mut buckets: HashMap<(u8, u64), Vec<usize>> = HashMap::new();
+    for (index, probe) in probes.iter().enumerate() {
+        for bucket in create_lsh_buckets(&probe.minhash, params.banding()) {
+            buckets
+                .entry((bucket.index(), bucket.hash()))
+                .or_default()
+                .push(index);
+        }
+    }
+
+    let mut result: HashMap<DocId, HashSet<usize>> = HashMap::new();
+    let mut merger = LshBucketRowsFilesMerger::new(&[(context.raw_lsh_buckets_folder_path(), 0)])?;
+    while merger.has_data_left() {
+        let row = merger.next()?;
+        if let Some(probes) = buckets.get(&(row.bucket_index(), row.bucket_hash())) {
+            result
+                .entry(row.doc_id())
+                .or_default()
+                .extend(probes.iter().copied());
+        }
+    }
+    Ok(result)
+}
+
+// Every colliding corpus document is matched with its most similar benchmark document
+pub fn find_contamination(
+    context: &Context,
+    params: &LshBucketsParams,
+    documents: &[BenchmarkDocument],
+    window: Option<usize>,
+    min_similarity: f64,
+    n_workers: usize,
+) -> Result<Vec<Match>> {
+    info!("Started finding contamination");
+
+    let probes = create_probes(documents, params, window);
+    let candidates = find_candidates(context, params, &probes)?;
+    info!("Found colliding corpus documents: {}", candidates.len());
+
+    let verification = Verification::MinHash { threshold: 0.0 };
+    let signatures = operations::load_signatures(
+        context,
+        params,
+        &verification,
+        candidates.keys().copied().collect(),
+        n_workers,
+    )?;
+
+    let mut matches = Vec::new();
+    for (doc_id, doc_probes) in candidates {
+        let minhash = match &signatures[&doc_id] {
+            Signature::MinHash(x) => x,
+            Signature::Shingles(_) => unreachable!(),
+        };
+        let (similarity, probe) = doc_probes
+            .iter()
+            .map(|x| (probes[*x].minhash.jaccard(minhash), *x))
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))
+            .unwrap();
+        if similarity < min_similarity {
+            continue;
+        }
+        matches.push(Match {
+            doc_id,
+            benchmark_id: documents[probes[probe].document].id.clone(),
+            similarity,
+        });
+    }
+    matches.sort_by_key(|x| x.doc_id);
+
+    info!(
+        "Stopped finding contamination, contaminated documents: {}",
+        matches.len()
+    );
+
+    Ok(matches)
+}
+
+pub fn write_report(context: &Context, matches: &[Match], report_path: &str) -> Result<()> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("source_path", DataType::Utf8, false),
+        Field::new("row_index", DataType::UInt32, false),
+        Field::new("benchmark_id", DataType::Utf8, false),
+        Field::new("similarity", DataType::Float64, false),
+    ]));
+    let source_path: StringArray = matches
+        .iter()
+        .map(|x| context.input_file(x.doc_id.file_index).map(Some))
+        .collect::<Result<_>>()?;
+    let row_index: UInt32Array = matches.iter().map(|x| Some(x.doc_id.row_index)).collect();
+    let benchmark_id: StringArray = matches
+        .iter()
+        .map(|x| Some(x.benchmark_id.as_str()))
+        .collect();
+    let similarity: Float64Array = matches.iter().map(|x| Some(x.similarity)).collect();
+    let record_batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(source_path),
+            Arc::new(row_index),
+            Arc::new(benchmark_id),
+            Arc::new(similarity),
+        ],
+    )?;
+
+    let mut writer = BatchWriter::new(report_path, schema)?;
+    writer.write(&record_batch)?;
+    writer.close()?;
+
+    info!("Contamination report is written to {}", report_path);
+
+    Ok(())
+}
+
+fn remove_from_files(
+    context: &Context,
+    file_indices: &[u32],
+    column: &TextColumns,
+    removed: &HashMap<u32, HashSet<u32>>,
+    output: &Output,
+    progress_bar: &ProgressBar,
+    removed_rows: &AtomicU64,
+) -> Result<()> {
+    let empty = HashSet::new();
+    for file_index in file_indices {
+        let file = context.input_file(*file_index)?;
+        let rows = removed.get(file_index).unwrap_or(&empty);
+
+        let output_file_path = output.file_path(file)?;
+        debug!("Writing {}", output_file_path);
+
+        let mut reader = BatchReader::try_new(file, column)?;
+        let mut writer = BatchWriter::new(&output_file_path, reader.schema())?;
+        let mut row_index = 0u32;
+        while let Some((record_batch, texts)) = reader.next()? {
+            let mask: BooleanArray = texts
+                .iter()
+                .zip(row_index..)
+                .map(|(text, i)| Some(!rows.contains(&i) && !column.is_skipped(text)))
+                .collect();
+            row_index += record_batch.num_rows() as u32;
+            removed_rows.fetch_add(mask.false_count() as u64, Ordering::Relaxed);
+            writer.write(&filter_record_batch(&record_batch, &mask)?)?;
+        }
+        output.add_manifest_entry(
+            file,
+            &output_file_path,
+            reader.num_rows(),
+            writer.num_rows(),
+        );
+        writer.close()?;
+
+        progress_bar.inc(1);
+    }
+    Ok(())
+}
+
+// Writes all input files without contaminated documents
+pub fn remove_contamination(
+    context: &Context,
+    column: &TextColumns,
+    matches: &[Match],
+    output: &Output,
+    n_workers: usize,
+) -> Result<()> {
+    info!("Started removing contamination");
+
+    let mut removed: HashMap<u32, HashSet<u32>> = HashMap::new();
+    for x in matches {
+        removed
+            .entry(x.doc_id.file_index)
+            .or_default()
+            .insert(x.doc_id.row_index);
+    }
+    let removed = Arc::new(removed);
+
+    let input_files: Vec<u32> = (0..context.input_files().len() as u32).collect();
+    let removed_rows = Arc::new(AtomicU64::new(0));
+    let progress_bar = Arc::new(ProgressBar::new(input_files.len() as u64));
+    let pool = ThreadPool::new(n_workers);
+
+    let num_files_per_worker = cmp::max(input_files.len() / n_workers, 1);
+    for start in (0..input_files.len()).step_by(num_files_per_worker) {
+        let worker_files = Vec::from(
+            &input_files[start..cmp::min(input_files.len(), start + num_files_per_worker)],
+        );
+        let context = context.clone();
+        let column = column.clone();
+        let removed = removed.clone();
+        let output = output.clone();
+        let progress_bar = progress_bar.clone();
+        let removed_rows = removed_rows.clone();
+        let worker = move || {
+            let result = remove_from_files(
+                &context,
+                &worker_files,
+                &column,
+                &removed,
+                &output,
+                &progress_bar,
+                &removed_rows,
+            );
+            if result.is_err() {
+                error!("{}", result.err().unwrap());
+                panic!();
+            }
+        };
+        pool.execute(worker);
+    }
+    operations::join_workers(&pool)?;
+
+    info!(
+        "Stopped removing contamination, removed rows: {}",
+        removed_rows.load(Ordering::Relaxed)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::Path};
+
+    fn temp_path(name: &str) -> String {
+        let folder = env::temp_dir().join(format!("contamination_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&folder).unwrap();
+        folder.join(name).display().to_string()
+    }
+
+    #[test]
+    fn jsonl_benchmark_reads_nested_paths_and_skips_nulls() {
+        let path = temp_path("bench.jsonl");
+        let lines = [
+            r#"{"meta":{"text":"first","id":7}}"#,
+            r#"{"meta":{"text":null,"id":8}}"#,
+            "",
+            r#"{"other":1}"#,
+            r#"{"meta":{"text":"fifth"}}"#,
+        ];
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        for id_column in ["meta.id", "/meta/id"] {
+            let documents = read_jsonl_benchmark(&path, "meta.text", Some(id_column)).unwrap();
+            let documents: Vec<(&str, &str)> = documents
+                .iter()
+                .map(|x| (x.id.as_str(), x.text.as_str()))
+                .collect();
+            let fallback = format!("{}:4", path);
+            assert_eq!(
+                documents,
+                vec![("7", "first"), (fallback.as_str(), "fifth")]
+            );
+        }
+    }
+
+    #[test]
+    fn parquet_benchmark_ids_are_row_indices() {
+        let path = temp_path("bench.parquet");
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "content",
+            DataType::Utf8,
+            true,
+        )]));
+        let texts = StringArray::from(vec![None, Some("second"), None, Some("fourth")]);
+        let record_batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(texts)]).unwrap();
+        let mut writer = BatchWriter::new(&path, schema).unwrap();
+        writer.write(&record_batch).unwrap();
+        writer.close().unwrap();
+
+        let documents = read_parquet_benchmark(&path, "content", None).unwrap();
+        let ids: Vec<String> = documents.iter().map(|x| x.id.clone()).collect();
+        assert_eq!(ids, vec![format!("{}:1", path), format!("{}:3", path)]);
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+}
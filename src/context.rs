@@ -1,24 +1,34 @@
-use anyhow::Result;
-use cityhasher::hash;
+use anyhow::{anyhow, Result};
+use speedy::{Readable, Writable};
 use std::{
-    collections::HashMap,
     fs::create_dir_all,
     path::{Component, Path, PathBuf},
 };
 
+// Stable identity of a document: index of the file in sorted input files
+// and index of the row in the file
+#[derive(Readable, Writable, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct DocId {
+    pub file_index: u32,
+    pub row_index: u32,
+}
+
 #[derive(Clone)]
 pub struct Context {
     input_folder: String,
     tmp: String,
     input_files: Vec<String>,
-    hash_to_input_file: HashMap<u16, Vec<usize>>,
+    // lsh rows of already accepted corpus, its documents are never removed
+    reference_folder: Option<String>,
+    // source files of reference lsh rows, they go after input files in documents ids
+    reference_files: Vec<String>,
 }
 
 impl Context {
     pub fn new(input_folder: String, pattern: String, tmp: String) -> Result<Self> {
         let walker =
             globwalk::GlobWalkerBuilder::from_patterns(&input_folder, &[pattern]).build()?;
-        let input_files: Vec<String> = walker
+        let mut input_files: Vec<String> = walker
             .into_iter()
             .filter_map(Result::ok)
             .map(|x| {
@@ -29,31 +39,38 @@ impl Context {
                     .to_string()
             })
             .collect();
-
-        let mut hash_to_input_file: HashMap<u16, Vec<usize>> = HashMap::new();
-        for (i, path) in (&input_files).iter().enumerate() {
-            hash_to_input_file
-                .entry(Self::hash_path(&path))
-                .or_default()
-                .push(i);
-        }
+        // indices of input files are used in documents ids, so the order must be stable
+        input_files.sort();
 
         create_dir_all(format!("{}/filters", tmp))?;
 
+        // input files are resolved by the file system, so the folder must be resolved the same way
+        // to strip it from their paths
         Ok(Self {
-            input_folder: Self::canonicalize(&input_folder),
+            input_folder: std::fs::canonicalize(&input_folder)?.display().to_string(),
             tmp: Self::canonicalize(&tmp),
             input_files,
-            hash_to_input_file,
+            reference_folder: None,
+            reference_files: Vec::new(),
         })
     }
 
-    pub fn hash_path(path: &String) -> u16 {
-        let x = hash::<u32>(path);
-        ((x >> 16) ^ x) as u16
+    pub fn with_reference(self, reference_folder: String, reference_files: Vec<String>) -> Self {
+        Self {
+            reference_folder: Some(Self::canonicalize(&reference_folder)),
+            reference_files,
+            ..self
+        }
+    }
+
+    pub fn input_files_hash(&self) -> u64 {
+        let mut files = self.input_files.clone();
+        files.push(self.reference_folder.clone().unwrap_or_default());
+        files.extend(self.reference_files.iter().cloned());
+        cityhasher::hash::<u64>(files.join("\n"))
     }
 
-    pub fn canonicalize(path: &String) -> String {
+    pub fn canonicalize(path: &str) -> String {
         let path = Path::new(path);
         let mut components = path.components().peekable();
         let mut ret = if let Some(c @ Component::Prefix(..)) = components.peek().cloned() {
@@ -85,16 +102,37 @@ impl Context {
         &self.input_files
     }
 
-    pub fn hash_to_input_files(&self, hash: u16) -> Vec<String> {
-        if !self.hash_to_input_file.contains_key(&hash) {
-            return Vec::new();
+    // Fails for file indices of another run, e.g. if input files are changed since it
+    pub fn input_file(&self, index: u32) -> Result<&String> {
+        let position = index as usize;
+        if position < self.input_files.len() {
+            Ok(&self.input_files[position])
+        } else {
+            self.reference_files
+                .get(position - self.input_files.len())
+                .ok_or(anyhow!("Cannot find input file with index {}", index))
         }
+    }
 
-        let mut result = Vec::new();
-        for index in self.hash_to_input_file.get(&hash).unwrap() {
-            result.push(self.input_files[*index].clone());
-        }
-        result
+    pub fn reference_folder(&self) -> Option<&String> {
+        self.reference_folder.as_ref()
+    }
+
+    pub fn reference_file_index_offset(&self) -> u32 {
+        self.input_files.len() as u32
+    }
+
+    pub fn is_reference(&self, doc_id: &DocId) -> bool {
+        doc_id.file_index >= self.reference_file_index_offset()
+    }
+
+    // Input path and row index, unlike document id it does not depend on other input files
+    pub fn document_name(&self, doc_id: &DocId) -> Result<String> {
+        Ok(format!(
+            "{}:{}",
+            self.input_file(doc_id.file_index)?,
+            doc_id.row_index
+        ))
     }
 
     pub fn raw_lsh_buckets_folder_path(&self) -> String {
@@ -111,8 +149,79 @@ impl Context {
         Self::canonicalize(&path)
     }
 
-    pub fn filter_file_path(&self, path_hash: u16) -> String {
-        let path = format!("{}/filters/{}.filter", self.tmp, path_hash);
+    pub fn duplicates_clusters_path(&self) -> String {
+        let path = format!("{}/duplicates.clusters", self.tmp);
+        Self::canonicalize(&path)
+    }
+
+    pub fn run_meta_path(&self) -> String {
+        let path = format!("{}/run.meta", self.tmp);
+        Self::canonicalize(&path)
+    }
+
+    pub fn run_report_path(&self) -> String {
+        let path = format!("{}/report.json", self.tmp);
+        Self::canonicalize(&path)
+    }
+
+    pub fn allowlist_path(&self) -> String {
+        let path = format!("{}/allowlist.txt", self.tmp);
+        Self::canonicalize(&path)
+    }
+
+    pub fn suffix_arrays_folder_path(&self) -> String {
+        let path = format!("{}/suffix_arrays", self.tmp);
+        Self::canonicalize(&path)
+    }
+
+    pub fn suffix_array_shard_path(&self, shard_index: u32, extension: &str) -> String {
+        let path = format!(
+            "{}/suffix_arrays/shard_{}.{}",
+            self.tmp, shard_index, extension
+        );
+        Self::canonicalize(&path)
+    }
+
+    pub fn removed_ranges_file_path(&self, file_index: u32) -> String {
+        let path = format!("{}/suffix_arrays/{}.ranges", self.tmp, file_index);
         Self::canonicalize(&path)
     }
+
+    pub fn filters_folder_path(&self) -> String {
+        let path = format!("{}/filters", self.tmp);
+        Self::canonicalize(&path)
+    }
+
+    pub fn filter_file_path(&self, file_index: u32) -> String {
+        let path = format!("{}/filters/{}.filter", self.tmp, file_index);
+        Self::canonicalize(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn unknown_file_indices_are_errors() {
+        let folder = env::temp_dir().join(format!("context_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(folder.join("input")).unwrap();
+        fs::write(folder.join("input/a.parquet"), "").unwrap();
+        let context = Context::new(
+            folder.join("input").display().to_string(),
+            "*.parquet".to_string(),
+            folder.join("tmp").display().to_string(),
+        )
+        .unwrap();
+
+        assert!(context.input_file(0).unwrap().ends_with("/a.parquet"));
+        assert!(context.input_file(1).is_err());
+        let doc_id = DocId {
+            file_index: 1,
+            row_index: 0,
+        };
+        assert!(context.document_name(&doc_id).is_err());
+        fs::remove_dir_all(folder).unwrap();
+    }
 }
@@ -1,6 +1,51 @@
+use crate::context::DocId;
+use anyhow::Result;
 use console::{style, Style};
-use similar::{ChangeTag, TextDiff};
-use std::fmt;
+use html_escape::encode_text;
+use log::info;
+use similar::{ChangeTag, InlineChange, TextDiff};
+use std::{cmp, fmt, fs};
+
+const HTML_STYLE: &str = "
+body { font-family: sans-serif; margin: 20px; }
+table { border-collapse: collapse; width: 100%; table-layout: fixed; margin-bottom: 20px; }
+th { text-align: left; background: #eee; padding: 4px; font-weight: normal; }
+td { font-family: monospace; white-space: pre-wrap; word-break: break-all; vertical-align: top; padding: 0 4px; }
+td.n { width: 40px; color: #999; text-align: right; }
+td.del { background: #fee; }
+td.ins { background: #efe; }
+td.del em { background: #fbb; font-style: normal; }
+td.ins em { background: #bfb; font-style: normal; }
+";
+
+// Which clusters are shown
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum DiffSampling {
+    // clusters in order of the clusters file
+    First,
+    Random,
+    // random clusters, equal number from every power of two range of cluster sizes
+    Stratified,
+    // clusters with most duplicates in the whole clusters file
+    Largest,
+}
+
+pub struct DiffMember {
+    pub doc_id: DocId,
+    pub path: String,
+    // Jaccard similarity to the survivor estimated by minhash signatures
+    pub similarity: f64,
+    pub text: String,
+}
+
+// Survivor and duplicates of a cluster which pass similarity filter,
+// members are all documents of the cluster
+pub struct DiffCluster {
+    pub cluster_id: u64,
+    pub members: Vec<DocId>,
+    pub survivor: DiffMember,
+    pub duplicates: Vec<DiffMember>,
+}
 
 struct Line(Option<usize>);
 
@@ -43,3 +88,127 @@ pub fn print_diff(old: &str, new: &str) {
         }
     }
 }
+
+pub fn print_cluster(cluster: &DiffCluster) {
+    println!("{:=^1$}", "=", 80);
+    println!(
+        "{}",
+        style(format!(
+            "Cluster {}, size {}",
+            cluster.cluster_id,
+            cluster.members.len()
+        ))
+        .bold()
+    );
+    for duplicate in &cluster.duplicates {
+        println!(
+            "{} {}:{}",
+            style("- survivor").red(),
+            cluster.survivor.path,
+            cluster.survivor.doc_id.row_index
+        );
+        println!(
+            "{} {}:{}, similarity {:.3}",
+            style("+ duplicate").green(),
+            duplicate.path,
+            duplicate.doc_id.row_index,
+            duplicate.similarity
+        );
+        print_diff(&cluster.survivor.text, &duplicate.text);
+    }
+}
+
+// Line of a change with emphasized inline changes
+fn html_line(change: &InlineChange<str>) -> String {
+    let mut line = String::new();
+    for (emphasized, value) in change.iter_strings_lossy() {
+        let value = encode_text(value.trim_end_matches('\n')).to_string();
+        if emphasized {
+            line.push_str(&format!("<em>{}</em>", value));
+        } else {
+            line.push_str(&value);
+        }
+    }
+    line
+}
+
+fn html_cells(class: &str, line: Option<&(Option<usize>, String)>) -> String {
+    match line {
+        Some((index, line)) => format!(
+            "<td class=\"n\">{}</td><td class=\"{}\">{}</td>",
+            index.map_or(String::new(), |x| (x + 1).to_string()),
+            class,
+            line
+        ),
+        None => "<td class=\"n\"></td><td></td>".to_string(),
+    }
+}
+
+// Side by side diff, deleted and inserted lines of every op are put on the same rows
+fn html_diff(old: &str, new: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let mut rows = String::new();
+    for op in diff.ops() {
+        let mut deleted = Vec::new();
+        let mut inserted = Vec::new();
+        for change in diff.iter_inline_changes(op) {
+            match change.tag() {
+                ChangeTag::Equal => {
+                    let line = html_line(&change);
+                    rows.push_str(&format!(
+                        "<tr>{}{}</tr>\n",
+                        html_cells("eq", Some(&(change.old_index(), line.clone()))),
+                        html_cells("eq", Some(&(change.new_index(), line)))
+                    ));
+                }
+                ChangeTag::Delete => deleted.push((change.old_index(), html_line(&change))),
+                ChangeTag::Insert => inserted.push((change.new_index(), html_line(&change))),
+            }
+        }
+        for i in 0..cmp::max(deleted.len(), inserted.len()) {
+            rows.push_str(&format!(
+                "<tr>{}{}</tr>\n",
+                html_cells("del", deleted.get(i)),
+                html_cells("ins", inserted.get(i))
+            ));
+        }
+    }
+    rows
+}
+
+// Self-contained page with side by side diffs of every duplicate with the survivor
+pub fn write_html_report(path: &str, clusters: &[DiffCluster]) -> Result<()> {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Duplicates clusters</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1>Duplicates clusters: {}</h1>\n",
+        HTML_STYLE,
+        clusters.len()
+    );
+    for cluster in clusters {
+        html.push_str(&format!(
+            "<h2>Cluster {}, size {}</h2>\n",
+            cluster.cluster_id,
+            cluster.members.len()
+        ));
+        for duplicate in &cluster.duplicates {
+            html.push_str(&format!(
+                "<table>\n<tr><th colspan=\"2\">survivor {}:{}</th>\
+                 <th colspan=\"2\">duplicate {}:{}, similarity {:.3}</th></tr>\n",
+                encode_text(&cluster.survivor.path),
+                cluster.survivor.doc_id.row_index,
+                encode_text(&duplicate.path),
+                duplicate.doc_id.row_index,
+                duplicate.similarity
+            ));
+            html.push_str(&html_diff(&cluster.survivor.text, &duplicate.text));
+            html.push_str("</table>\n");
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    fs::write(path, html)?;
+
+    info!("Html report is written to {}", path);
+
+    Ok(())
+}
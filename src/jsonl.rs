@@ -0,0 +1,387 @@
+use anyhow::{anyhow, Result};
+use arrow::{
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    util::display::array_value_to_string,
+};
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type, UInt32Type, UInt64Type},
+    Array, ArrayRef, RecordBatch, StringArray,
+};
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    sync::Arc,
+};
+
+// Column with the whole source line, lines are written back untouched
+// unless their text or additional columns are changed
+pub const JSONL_LINE_COLUMN: &str = "__jsonl_line";
+// Metadata key of the text column with its json path
+const JSON_PATH_KEY: &str = "json_path";
+const BATCH_SIZE: usize = 1024;
+
+// .json files are read as json lines as well, one object per line
+pub fn is_jsonl(path: &str) -> bool {
+    [
+        ".jsonl",
+        ".jsonl.gz",
+        ".jsonl.zst",
+        ".json",
+        ".json.gz",
+        ".json.zst",
+    ]
+    .iter()
+    .any(|x| path.ends_with(x))
+}
+
+// Json pointer of the path, dotted paths like `meta.text` are supported as well
+pub fn json_pointer(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path.replace('.', "/"))
+    }
+}
+
+pub fn open_lines(path: &str) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read + Send> = if path.ends_with(".gz") {
+        Box::new(MultiGzDecoder::new(file))
+    } else if path.ends_with(".zst") {
+        Box::new(zstd::stream::Decoder::new(file)?)
+    } else {
+        Box::new(file)
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+pub struct JsonlBatches {
+    path: String,
+    pointers: Vec<String>,
+    schema: SchemaRef,
+    lines: Box<dyn BufRead + Send>,
+    line_number: usize,
+}
+
+impl JsonlBatches {
+    // Every column is a json path of a text field
+    pub fn try_new(path: &str, columns: &[String]) -> Result<Self> {
+        let mut fields: Vec<Field> = columns
+            .iter()
+            .map(|x| {
+                Field::new(x, DataType::Utf8, true)
+                    .with_metadata(HashMap::from([(JSON_PATH_KEY.to_string(), x.to_string())]))
+            })
+            .collect();
+        fields.push(Field::new(JSONL_LINE_COLUMN, DataType::Utf8, false));
+        Ok(Self {
+            path: path.to_string(),
+            pointers: columns.iter().map(|x| json_pointer(x)).collect(),
+            schema: Arc::new(Schema::new(fields)),
+            lines: open_lines(path)?,
+            line_number: 0,
+        })
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn read_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let mut texts = vec![Vec::new(); self.pointers.len()];
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        while lines.len() < BATCH_SIZE {
+            line.clear();
+            if self.lines.read_line(&mut line)? == 0 {
+                break;
+            }
+            self.line_number += 1;
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value: Value = serde_json::from_str(line).map_err(|e| {
+                anyhow!(
+                    "Cannot parse line {} of {}: {}",
+                    self.line_number,
+                    self.path,
+                    e
+                )
+            })?;
+            for (texts, pointer) in texts.iter_mut().zip(&self.pointers) {
+                texts.push(
+                    value
+                        .pointer(pointer)
+                        .and_then(|x| x.as_str())
+                        .map(|x| x.to_string()),
+                );
+            }
+            lines.push(line.to_string());
+        }
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        let mut columns: Vec<ArrayRef> = texts
+            .into_iter()
+            .map(|x| Arc::new(StringArray::from(x)) as ArrayRef)
+            .collect();
+        columns.push(Arc::new(StringArray::from(lines)));
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+}
+
+impl Iterator for JsonlBatches {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_batch().transpose()
+    }
+}
+
+fn json_value(array: &dyn Array, index: usize) -> Result<Value> {
+    if array.is_null(index) {
+        return Ok(Value::Null);
+    }
+    Ok(match array.data_type() {
+        DataType::Boolean => Value::from(array.as_boolean().value(index)),
+        DataType::UInt32 => Value::from(array.as_primitive::<UInt32Type>().value(index)),
+        DataType::UInt64 => Value::from(array.as_primitive::<UInt64Type>().value(index)),
+        DataType::Int64 => Value::from(array.as_primitive::<Int64Type>().value(index)),
+        DataType::Float64 => Value::from(array.as_primitive::<Float64Type>().value(index)),
+        DataType::Utf8 => Value::from(array.as_string::<i32>().value(index)),
+        _ => Value::from(array_value_to_string(array, index)?),
+    })
+}
+
+// Sets value by json pointer, missing objects on the way are created
+fn set_pointer(root: &mut Value, pointer: &str, value: Value) {
+    let mut current = root;
+    for key in pointer.split('/').skip(1) {
+        if !current.is_object() {
+            *current = Value::Object(Default::default());
+        }
+        current = current
+            .as_object_mut()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert(Value::Null);
+    }
+    *current = value;
+}
+
+// Compression of the output file, it is finished explicitly on close,
+// so errors of writing the gzip trailer or the zstd frame end are not lost in drop
+enum JsonlSink {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::stream::Encoder<'static, BufWriter<File>>),
+}
+
+impl Write for JsonlSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            JsonlSink::Plain(x) => x.write(buf),
+            JsonlSink::Gzip(x) => x.write(buf),
+            JsonlSink::Zstd(x) => x.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            JsonlSink::Plain(x) => x.flush(),
+            JsonlSink::Gzip(x) => x.flush(),
+            JsonlSink::Zstd(x) => x.flush(),
+        }
+    }
+}
+
+// Writes rows as json lines, source lines are taken from the line column,
+// the text column is written by its json path and other columns as top level fields,
+// which must not be in source lines already
+pub struct JsonlWriter {
+    path: String,
+    writer: JsonlSink,
+    schema: SchemaRef,
+}
+
+impl JsonlWriter {
+    pub fn new(path: &str, schema: SchemaRef) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let writer = if path.ends_with(".gz") {
+            JsonlSink::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+        } else if path.ends_with(".zst") {
+            JsonlSink::Zstd(zstd::stream::Encoder::new(file, 5)?)
+        } else {
+            JsonlSink::Plain(file)
+        };
+        Ok(Self {
+            path: path.to_string(),
+            writer,
+            schema,
+        })
+    }
+
+    pub fn write(&mut self, record_batch: &RecordBatch) -> Result<()> {
+        let lines = record_batch
+            .column_by_name(JSONL_LINE_COLUMN)
+            .map(|x| x.as_string::<i32>());
+        for row in 0..record_batch.num_rows() {
+            let source = lines.map(|x| x.value(row));
+            let mut value = match source {
+                Some(line) => serde_json::from_str(line)?,
+                None => Value::Object(Default::default()),
+            };
+            let mut changed = source.is_none();
+            for (field, column) in self.schema.fields().iter().zip(record_batch.columns()) {
+                if field.name() == JSONL_LINE_COLUMN {
+                    continue;
+                }
+                let column_value = json_value(column, row)?;
+                match field.metadata().get(JSON_PATH_KEY) {
+                    Some(path) => {
+                        let pointer = json_pointer(path);
+                        // missing field is not added for a null text
+                        if value.pointer(&pointer).unwrap_or(&Value::Null) != &column_value {
+                            set_pointer(&mut value, &pointer, column_value);
+                            changed = true;
+                        }
+                    }
+                    None => {
+                        if source.is_some() && value.get(field.name()).is_some() {
+                            return Err(anyhow!(
+                                "Column {} conflicts with a field of source lines, output file {}",
+                                field.name(),
+                                self.path
+                            ));
+                        }
+                        set_pointer(&mut value, &format!("/{}", field.name()), column_value);
+                        changed = true;
+                    }
+                }
+            }
+            match source {
+                Some(line) if !changed => self.writer.write_all(line.as_bytes())?,
+                _ => serde_json::to_writer(&mut self.writer, &value)?,
+            }
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn close(self) -> Result<()> {
+        let mut file = match self.writer {
+            JsonlSink::Plain(x) => x,
+            JsonlSink::Gzip(x) => x.finish()?,
+            JsonlSink::Zstd(x) => x.finish()?,
+        };
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    const LINES: [&str; 3] = [
+        r#"{"id":1,"meta":{"text":"first"}}"#,
+        r#"{"id":2,"meta":{"text":"second"},"extra":[1,2]}"#,
+        r#"{"id":3}"#,
+    ];
+
+    fn read_lines(path: &str) -> Vec<String> {
+        open_lines(path)
+            .unwrap()
+            .lines()
+            .map(|x| x.unwrap())
+            .collect()
+    }
+
+    fn round_trip(extension: &str) {
+        let folder = env::temp_dir().join(format!("jsonl_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&folder).unwrap();
+        let input = folder.join("input.jsonl").display().to_string();
+        let output = folder
+            .join(format!("output{}", extension))
+            .display()
+            .to_string();
+        fs::write(&input, LINES.join("\n") + "\n").unwrap();
+
+        let batches = JsonlBatches::try_new(&input, &["meta.text".to_string()]).unwrap();
+        let mut writer = JsonlWriter::new(&output, batches.schema()).unwrap();
+        for batch in batches {
+            writer.write(&batch.unwrap()).unwrap();
+        }
+        writer.close().unwrap();
+
+        assert_eq!(read_lines(&output), LINES);
+        let mut batches = JsonlBatches::try_new(&output, &["meta.text".to_string()]).unwrap();
+        let batch = batches.next().unwrap().unwrap();
+        let texts = batch.column(0).as_string::<i32>();
+        assert_eq!(texts.value(1), "second");
+        assert!(texts.is_null(2));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn writes_plain_lines() {
+        round_trip(".jsonl");
+    }
+
+    #[test]
+    fn writes_gzip_lines() {
+        round_trip(".jsonl.gz");
+    }
+
+    #[test]
+    fn writes_zstd_lines() {
+        round_trip(".jsonl.zst");
+    }
+
+    #[test]
+    fn columns_conflicting_with_fields_of_lines_are_rejected() {
+        let folder = env::temp_dir().join(format!("jsonl_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&folder).unwrap();
+        let input = folder.join("input.jsonl").display().to_string();
+        let output = folder.join("output.jsonl").display().to_string();
+        fs::write(&input, LINES.join("\n") + "\n").unwrap();
+
+        let mut batches = JsonlBatches::try_new(&input, &["meta.text".to_string()]).unwrap();
+        let batch = batches.next().unwrap().unwrap();
+        let write_column = |name: &str| {
+            let mut fields: Vec<Field> = batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|x| x.as_ref().clone())
+                .collect();
+            fields.push(Field::new(name, DataType::UInt64, true));
+            let schema = Arc::new(Schema::new(fields));
+            let mut columns = batch.columns().to_vec();
+            columns.push(Arc::new(arrow_array::UInt64Array::from(vec![7; 3])));
+            let record_batch = RecordBatch::try_new(schema.clone(), columns).unwrap();
+            let mut writer = JsonlWriter::new(&output, schema).unwrap();
+            writer.write(&record_batch)
+        };
+        assert!(write_column("extra").is_err());
+        write_column("cluster_id").unwrap();
+        assert_eq!(
+            read_lines(&output)[2],
+            r#"{"id":3,"cluster_id":7}"#.to_string()
+        );
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn sets_changed_text_by_path() {
+        let mut value: Value = serde_json::from_str(LINES[2]).unwrap();
+        set_pointer(&mut value, &json_pointer("meta.text"), Value::from("new"));
+        assert_eq!(value.to_string(), r#"{"id":3,"meta":{"text":"new"}}"#);
+    }
+}
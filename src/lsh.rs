@@ -1,8 +1,16 @@
-use crate::{context::Context, minhash::MinHash};
+use crate::{
+    batch_io::TextColumns,
+    context::{Context, DocId},
+    minhash::{MinHash, MinHashParams},
+    normalization::Normalization,
+    segments::Segmentation,
+    survivor::SurvivorPolicy,
+};
 
 use anyhow::Result;
 use cityhasher::hash;
 use log::debug;
+use serde::Serialize;
 use speedy::{IsEof, Readable, Writable};
 use std::{
     cmp::Ordering,
@@ -16,10 +24,65 @@ use std::{
 use uuid::Uuid;
 use zstd::stream;
 
-// Settings to find duplicates with Jaccard similarity 0.8
-const LSH_RANGE: usize = 15;
-const LSH_BUCKETS: usize = 17;
-const LSH_LAST: usize = LSH_RANGE * LSH_BUCKETS;
+// Bucket index is stored in u8
+const MAX_LSH_BANDS: usize = 256;
+const INTEGRATION_STEPS: usize = 1000;
+
+// Number of bands and rows per band of lsh index, signature of every document
+// is split into bands, documents are candidates if they collide in any band
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct LshBanding {
+    bands: u32,
+    rows: u32,
+}
+
+fn integrate(f: impl Fn(f64) -> f64, a: f64, b: f64) -> f64 {
+    let step = (b - a) / INTEGRATION_STEPS as f64;
+    (0..INTEGRATION_STEPS)
+        .map(|i| f(a + (i as f64 + 0.5) * step) * step)
+        .sum()
+}
+
+impl LshBanding {
+    // Selects bands and rows which minimize weighted sum of false positive and
+    // false negative probabilities, i.e. areas under and above the S-curve
+    // 1 - (1 - s^rows)^bands around the threshold
+    pub fn optimal(
+        threshold: f64,
+        num_perm: usize,
+        false_positive_weight: f64,
+        false_negative_weight: f64,
+    ) -> Self {
+        let mut best = (f64::MAX, Self { bands: 1, rows: 1 });
+        for bands in 1..=num_perm.min(MAX_LSH_BANDS) {
+            for rows in 1..=(num_perm / bands) {
+                let probability = |s: f64| 1.0 - (1.0 - s.powi(rows as i32)).powi(bands as i32);
+                let false_positive = integrate(probability, 0.0, threshold);
+                let false_negative = integrate(|s| 1.0 - probability(s), threshold, 1.0);
+                let error =
+                    false_positive * false_positive_weight + false_negative * false_negative_weight;
+                if error < best.0 {
+                    best = (
+                        error,
+                        Self {
+                            bands: bands as u32,
+                            rows: rows as u32,
+                        },
+                    );
+                }
+            }
+        }
+        best.1
+    }
+
+    pub fn bands(&self) -> usize {
+        self.bands as usize
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows as usize
+    }
+}
 
 #[derive(Eq, PartialEq, Ord, PartialOrd)]
 pub struct LshBucket {
@@ -37,17 +100,18 @@ impl LshBucket {
     }
 }
 
-pub fn create_lsh_buckets(minhash: &MinHash) -> Vec<LshBucket> {
+pub fn create_lsh_buckets(minhash: &MinHash, banding: &LshBanding) -> Vec<LshBucket> {
     let mut result = Vec::new();
-    for (index, start) in (0..LSH_LAST).step_by(LSH_RANGE).enumerate() {
-        let slice = &minhash[start..(start + LSH_RANGE)];
+    let last = banding.bands() * banding.rows();
+    for (index, start) in (0..last).step_by(banding.rows()).enumerate() {
+        let slice = &minhash[start..(start + banding.rows())];
         let bytes: &[u8] = unsafe {
             slice::from_raw_parts(
                 slice.as_ptr() as *const u8,
                 slice.len() * mem::size_of_val(&slice[0]),
             )
         };
-        assert!(start <= 255 && index <= LSH_BUCKETS);
+        assert!(index < MAX_LSH_BANDS);
         result.push(LshBucket {
             index: index as u8,
             hash: hash::<u64>(bytes),
@@ -56,15 +120,156 @@ pub fn create_lsh_buckets(minhash: &MinHash) -> Vec<LshBucket> {
     result
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum DeduplicationModeKind {
+    Near,
+    Exact,
+    Lines,
+    Paragraphs,
+    Substrings,
+}
+
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeduplicationMode {
+    // Near duplicates from lsh buckets of minhash signatures
+    Near,
+    // Exact duplicates by content hash only, every document has a single row
+    // with the content hash as bucket hash, for 128 bits the bucket hash is
+    // an additional 64 bits hash of the content
+    Exact { hash_bits: u32 },
+    // Frequent lines or paragraphs are stripped from texts, every distinct segment
+    // of a document has a row with the segment hash as bucket hash
+    Segments { segmentation: Segmentation },
+    // Repeated spans of at least min_tokens tokens are removed from texts,
+    // they are found by suffix arrays instead of lsh rows
+    Substrings { min_tokens: u32 },
+}
+
+impl DeduplicationMode {
+    pub fn new(kind: DeduplicationModeKind, hash_bits: usize, min_substring_tokens: usize) -> Self {
+        match kind {
+            DeduplicationModeKind::Near => DeduplicationMode::Near,
+            DeduplicationModeKind::Exact => DeduplicationMode::Exact {
+                hash_bits: hash_bits as u32,
+            },
+            DeduplicationModeKind::Lines => DeduplicationMode::Segments {
+                segmentation: Segmentation::Lines,
+            },
+            DeduplicationModeKind::Paragraphs => DeduplicationMode::Segments {
+                segmentation: Segmentation::Paragraphs,
+            },
+            DeduplicationModeKind::Substrings => DeduplicationMode::Substrings {
+                min_tokens: min_substring_tokens as u32,
+            },
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        matches!(self, DeduplicationMode::Exact { .. })
+    }
+
+    pub fn segmentation(&self) -> Option<Segmentation> {
+        match self {
+            DeduplicationMode::Segments { segmentation } => Some(*segmentation),
+            _ => None,
+        }
+    }
+}
+
+// Parameters which define content of lsh rows files,
+// files built with other parameters are not compatible
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct LshBucketsParams {
+    text_columns: TextColumns,
+    survivor_policy: SurvivorPolicy,
+    minhash_params: MinHashParams,
+    banding: LshBanding,
+    normalization: Normalization,
+    mode: DeduplicationMode,
+}
+
+impl LshBucketsParams {
+    pub fn new(
+        text_columns: TextColumns,
+        survivor_policy: SurvivorPolicy,
+        minhash_params: MinHashParams,
+        banding: LshBanding,
+        normalization: Normalization,
+        mode: DeduplicationMode,
+    ) -> Self {
+        assert!(banding.bands() * banding.rows() <= minhash_params.num_perm());
+        Self {
+            text_columns,
+            survivor_policy,
+            minhash_params,
+            banding,
+            normalization,
+            mode,
+        }
+    }
+
+    pub fn text_columns(&self) -> &TextColumns {
+        &self.text_columns
+    }
+
+    pub fn survivor_policy(&self) -> &SurvivorPolicy {
+        &self.survivor_policy
+    }
+
+    pub fn minhash_params(&self) -> &MinHashParams {
+        &self.minhash_params
+    }
+
+    pub fn banding(&self) -> &LshBanding {
+        &self.banding
+    }
+
+    pub fn normalization(&self) -> &Normalization {
+        &self.normalization
+    }
+
+    pub fn mode(&self) -> &DeduplicationMode {
+        &self.mode
+    }
+
+    // Lsh rows built with compatible params can be merged, survivor policy does not affect buckets
+    pub fn is_compatible(&self, other: &LshBucketsParams) -> bool {
+        self.text_columns.is_compatible(&other.text_columns)
+            && self.minhash_params == other.minhash_params
+            && self.banding == other.banding
+            && self.normalization == other.normalization
+            && self.mode == other.mode
+    }
+}
+
+// Input file and its index in input files, rows refer to files by index,
+// so they are valid only while the file has the same index
+#[derive(Readable, Writable, PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub struct LshBucketsFile {
+    path: String,
+    index: u32,
+}
+
+impl LshBucketsFile {
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
 #[derive(Readable, Writable)]
 pub struct LshBucketsMeta {
-    files: Vec<String>,
-    column_name: String,
+    files: Vec<LshBucketsFile>,
+    params: LshBucketsParams,
     file_prefix: String,
 }
 
 impl LshBucketsMeta {
-    pub fn files(&self) -> &Vec<String> {
+    pub fn files(&self) -> &Vec<LshBucketsFile> {
         &self.files
     }
 
@@ -72,29 +277,40 @@ impl LshBucketsMeta {
         &self.file_prefix
     }
 
-    pub fn column_name(&self) -> &String {
-        &self.column_name
+    pub fn params(&self) -> &LshBucketsParams {
+        &self.params
     }
 }
 
+#[allow(clippy::repr_packed_without_abi)]
 #[repr(packed)]
 #[derive(Eq, PartialEq, Ord, PartialOrd, Readable, Writable, Copy, Clone)]
 pub struct LshBucketRow {
     bucket_index: u8,
     bucket_hash: u64,
-    // path hash is too small, but it is needed to show diff between texts,
-    // it speed up searching content by content_hash
-    path_hash: u16,
+    // document id is stored by fields, because row is packed
+    file_index: u32,
+    row_index: u32,
     content_hash: u64,
+    // rank of the document according to survivor policy
+    rank: u64,
 }
 
 impl LshBucketRow {
-    pub fn new(bucket_index: u8, bucket_hash: u64, path_hash: u16, content_hash: u64) -> Self {
+    pub fn new(
+        bucket_index: u8,
+        bucket_hash: u64,
+        doc_id: DocId,
+        content_hash: u64,
+        rank: u64,
+    ) -> Self {
         Self {
             bucket_index,
             bucket_hash,
-            path_hash,
+            file_index: doc_id.file_index,
+            row_index: doc_id.row_index,
             content_hash,
+            rank,
         }
     }
 
@@ -106,13 +322,20 @@ impl LshBucketRow {
         self.bucket_hash
     }
 
-    pub fn path_hash(&self) -> u16 {
-        self.path_hash
+    pub fn doc_id(&self) -> DocId {
+        DocId {
+            file_index: self.file_index,
+            row_index: self.row_index,
+        }
     }
 
     pub fn content_hash(&self) -> u64 {
         self.content_hash
     }
+
+    pub fn rank(&self) -> u64 {
+        self.rank
+    }
 }
 
 pub struct LshBucketRowsFilesWriter {
@@ -123,12 +346,12 @@ pub struct LshBucketRowsFilesWriter {
 }
 
 impl LshBucketRowsFilesWriter {
-    pub fn new(folder: String, buckets_size_limit: u64) -> Self {
+    pub fn new(folder: String, params: LshBucketsParams, buckets_size_limit: u64) -> Self {
         Self {
             folder,
             meta: LshBucketsMeta {
                 files: Vec::new(),
-                column_name: String::new(),
+                params,
                 file_prefix: String::new(),
             },
             rows: Vec::new(),
@@ -138,15 +361,15 @@ impl LshBucketRowsFilesWriter {
 
     pub fn write_rows(
         &mut self,
-        source_file: &String,
-        column_name: &String,
+        source_file: &str,
+        file_index: u32,
         rows: Vec<LshBucketRow>,
     ) -> Result<()> {
         self.rows.extend(rows);
-        self.meta.files.push(source_file.clone());
-
-        assert!(self.meta.column_name.is_empty() || self.meta.column_name == *column_name);
-        self.meta.column_name = column_name.clone();
+        self.meta.files.push(LshBucketsFile {
+            path: source_file.to_string(),
+            index: file_index,
+        });
 
         if !self.rows.is_empty()
             && (mem::size_of_val(&self.rows[0]) as u64) * (self.rows.len() as u64)
@@ -210,13 +433,19 @@ impl LshBucketRowsFilesWriter {
 pub struct LshBucketRowsFileReader<'a> {
     reader: stream::read::Decoder<'a, BufReader<File>>,
     prev: Option<LshBucketRow>,
+    // added to file indices of rows, so rows of reference files get their own indices
+    file_index_offset: u32,
 }
 
 impl LshBucketRowsFileReader<'_> {
-    pub fn new(path: &String) -> Result<Self> {
-        let file = File::open(&path)?;
+    pub fn new(path: &str, file_index_offset: u32) -> Result<Self> {
+        let file = File::open(path)?;
         let reader = stream::read::Decoder::new(file)?;
-        Ok(Self { reader, prev: None })
+        Ok(Self {
+            reader,
+            prev: None,
+            file_index_offset,
+        })
     }
 
     pub fn next(&mut self) -> Result<Option<LshBucketRow>> {
@@ -224,7 +453,8 @@ impl LshBucketRowsFileReader<'_> {
         if result.as_ref().is_err_and(|e| e.is_eof()) {
             return Ok(None);
         }
-        let result = result?;
+        let mut result = result?;
+        result.file_index += self.file_index_offset;
 
         if let Some(prev) = self.prev {
             assert!(
@@ -233,7 +463,7 @@ impl LshBucketRowsFileReader<'_> {
                         && prev.bucket_hash <= result.bucket_hash)
             );
         }
-        self.prev = Some(result.clone());
+        self.prev = Some(result);
         Ok(Some(result))
     }
 }
@@ -265,27 +495,23 @@ pub struct LshBucketRowsFilesMerger<'a> {
 }
 
 impl LshBucketRowsFilesMerger<'_> {
-    pub fn new(folder: &String) -> Result<Self> {
-        let list = read_dir(folder)?;
+    // Merges rows files of all folders, every folder has its own offset of file indices
+    pub fn new(folders: &[(String, u32)]) -> Result<Self> {
         let mut readers = Vec::new();
-        for path in list {
-            let path = path.unwrap().path().display().to_string();
-            if !path.ends_with(".lsh_rows") {
-                continue;
+        for (folder, file_index_offset) in folders {
+            for path in read_dir(folder)? {
+                let path = path.unwrap().path().display().to_string();
+                if !path.ends_with(".lsh_rows") {
+                    continue;
+                }
+                readers.push(LshBucketRowsFileReader::new(&path, *file_index_offset)?);
             }
-            readers.push(LshBucketRowsFileReader::new(&path)?);
         }
 
         let mut heap = BinaryHeap::new();
         for (index, reader) in readers.iter_mut().enumerate() {
-            let next = reader.next()?;
-            if next.is_some() {
-                heap.push((
-                    ReverseOrderedLshBucketRow {
-                        data: next.unwrap(),
-                    },
-                    index,
-                ));
+            if let Some(next) = reader.next()? {
+                heap.push((ReverseOrderedLshBucketRow { data: next }, index));
             }
         }
 
@@ -303,18 +529,43 @@ impl LshBucketRowsFilesMerger<'_> {
     pub fn next(&mut self) -> Result<LshBucketRow> {
         let top = self.heap.pop().unwrap();
         let reader = &mut self.readers[top.1];
-        let next = reader.next()?;
-        if next.is_some() {
-            self.heap.push((
-                ReverseOrderedLshBucketRow {
-                    data: next.unwrap(),
-                },
-                top.1,
-            ))
+        if let Some(next) = reader.next()? {
+            self.heap
+                .push((ReverseOrderedLshBucketRow { data: next }, top.1))
         }
         let result = top.0.data;
         assert!(self.prev.is_none() || self.prev.unwrap() <= result);
-        self.prev = Some(result.clone());
+        self.prev = Some(result);
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Similarity where the S-curve of the banding is the steepest
+    fn curve_threshold(banding: &LshBanding) -> f64 {
+        (1.0 / banding.bands() as f64).powf(1.0 / banding.rows() as f64)
+    }
+
+    #[test]
+    fn optimal_banding_follows_threshold() {
+        let mut previous = 0.0;
+        for threshold in [0.5, 0.7, 0.9] {
+            let banding = LshBanding::optimal(threshold, 256, 0.5, 0.5);
+            assert!(banding.bands() * banding.rows() <= 256);
+            let curve = curve_threshold(&banding);
+            assert!((curve - threshold).abs() < 0.1);
+            assert!(curve > previous);
+            previous = curve;
+        }
+    }
+
+    #[test]
+    fn false_negative_weight_lowers_curve() {
+        let balanced = LshBanding::optimal(0.8, 128, 0.5, 0.5);
+        let recall = LshBanding::optimal(0.8, 128, 0.1, 0.9);
+        assert!(curve_threshold(&recall) < curve_threshold(&balanced));
+    }
+}
@@ -1,26 +1,53 @@
 use std::{
-    fs::{create_dir_all, remove_dir_all},
+    fs::{create_dir_all, remove_dir_all, remove_file},
     path::Path,
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use batch_io::{ColumnsCombination, NullPolicy, Output, OutputFormat, TextColumns};
 use clap::Parser;
 use context::Context;
+use diff::DiffSampling;
 use env_logger::Env;
+use jsonl::is_jsonl;
 use log::info;
+use lsh::{DeduplicationMode, DeduplicationModeKind, LshBanding, LshBucketsParams};
+use minhash::{MinHashParams, Shingling, ShinglingKind, MAX_NUM_PERM};
+use normalization::{Normalization, NormalizationStep};
+use operations::{DiffOptions, FilterMode};
+use report::RunReport;
+use run_meta::RunMeta;
+use survivor::{SurvivorPolicy, SurvivorPolicyKind};
+use tokenizer::{Tokenizer, TokenizerKind};
+use verification::{Verification, VerificationKind};
 
+mod batch_io;
+mod contamination;
 mod context;
 mod diff;
+mod jsonl;
 mod lsh;
 mod minhash;
+mod normalization;
 mod operations;
-mod parquet_io;
+mod report;
+mod run_meta;
+mod segments;
+mod substrings;
+mod survivor;
+mod tokenizer;
+mod tui;
+mod union_find;
+mod verification;
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Parser)]
 #[command(name = "deduplicate")]
 enum Cli {
     Deduplicate(DeduplicateArgs),
     Diff(DiffArgs),
+    Contamination(ContaminationArgs),
+    ExportClusters(ExportClustersArgs),
 }
 
 #[derive(clap::Args)]
@@ -41,8 +68,26 @@ struct DeduplicateArgs {
     #[arg(long, value_name = "N_WORKERS", default_value = "1")]
     n_workers: usize,
 
-    #[arg(long, value_name = "COLUMN", default_value = "content")]
-    column: String,
+    /// Text columns, texts of several columns are joined or weighted in minhash signatures
+    #[arg(
+        long,
+        value_name = "COLUMN",
+        value_delimiter = ',',
+        default_value = "content"
+    )]
+    column: Vec<String>,
+
+    /// Separator of joined texts of several columns
+    #[arg(long, default_value = "\n")]
+    column_separator: String,
+
+    /// Weights of columns in minhash signatures instead of joining their texts, one per column
+    #[arg(long, value_delimiter = ',')]
+    column_weights: Vec<u32>,
+
+    /// Columns of exact content hash, text columns by default
+    #[arg(long, value_delimiter = ',')]
+    hash_column: Vec<String>,
 
     #[arg(long, value_name = "CLEAR", default_value = "false")]
     clear: bool,
@@ -51,6 +96,108 @@ struct DeduplicateArgs {
     //clear: bool,
     #[arg(long, default_value = "1073741824")]
     lsh_buckets_size_limit: u64,
+
+    /// Near duplicates by minhash lsh, exact duplicates by content hash only,
+    /// stripping of frequent lines or paragraphs or of repeated substrings from texts
+    #[arg(long, value_enum, default_value = "near")]
+    mode: DeduplicationModeKind,
+
+    /// Size of content hash in exact mode, 64 or 128 bits
+    #[arg(long, default_value = "64", value_parser = ["64", "128"])]
+    exact_hash_bits: String,
+
+    /// Which document of a duplicates cluster is kept
+    #[arg(long, value_enum, default_value = "content-hash")]
+    survivor_policy: SurvivorPolicyKind,
+
+    /// Timestamp column for earliest/latest policies or score column for highest policy
+    #[arg(long, value_name = "SURVIVOR_COLUMN")]
+    survivor_column: Option<String>,
+
+    /// Glob over canonical absolute input paths for source-priority policy,
+    /// first matching glob has top priority
+    #[arg(long, value_name = "SOURCE_PRIORITY")]
+    source_priority: Vec<String>,
+
+    /// Jaccard similarity of documents which are considered duplicates
+    #[arg(long, default_value = "0.8")]
+    threshold: f64,
+
+    /// Weight of false positive probability in selection of lsh bands and rows
+    #[arg(long, default_value = "0.5")]
+    false_positive_weight: f64,
+
+    /// Weight of false negative probability in selection of lsh bands and rows
+    #[arg(long, default_value = "0.5")]
+    false_negative_weight: f64,
+
+    /// Number of permutations in minhash signatures
+    #[arg(long, default_value = "256")]
+    num_perm: usize,
+
+    /// Shingles which documents similarity is computed on, word n-grams or character k-grams
+    #[arg(long, value_enum, default_value = "word")]
+    shingle: ShinglingKind,
+
+    /// Size of shingles, 1 for words and 5 for characters by default
+    #[arg(long)]
+    shingle_size: Option<usize>,
+
+    /// Tokenizer of texts, unicode word segmentation or legacy latin and cyrillic only splitter
+    #[arg(long, value_enum, default_value = "unicode")]
+    tokenizer: TokenizerKind,
+
+    /// Documents with less tokens are never considered near duplicates
+    #[arg(long, default_value = "5")]
+    min_tokens: usize,
+
+    /// Normalization steps applied in the given order before content hashing and minhashing
+    #[arg(long, value_enum, value_delimiter = ',')]
+    normalize: Vec<NormalizationStep>,
+
+    /// How candidate pairs from lsh buckets are verified before clustering
+    #[arg(long, value_enum, default_value = "none")]
+    verify: VerificationKind,
+
+    /// Minimal Jaccard similarity of verified candidate pairs, threshold by default
+    #[arg(long)]
+    verify_threshold: Option<f64>,
+
+    /// Lsh rows folder of already deduplicated corpus, its documents are never removed
+    #[arg(long, value_name = "REFERENCE")]
+    reference: Option<String>,
+
+    /// Lines or paragraphs found in more documents are stripped in lines and paragraphs modes
+    #[arg(long, default_value = "100")]
+    max_segment_frequency: u64,
+
+    /// Repeated spans of at least this number of words are removed in substrings mode
+    #[arg(long, default_value = "50")]
+    min_substring_tokens: usize,
+
+    /// Number of tokens in every shard of suffix arrays in substrings mode
+    #[arg(long, default_value = "100000000")]
+    suffix_array_shard_tokens: usize,
+
+    /// Remove duplicates from output files or keep all rows and annotate duplicates
+    #[arg(long, value_enum, default_value = "remove")]
+    filter_mode: FilterMode,
+
+    /// Format of output files, jsonl files are always written as jsonl
+    #[arg(long, value_enum, default_value = "auto")]
+    output_format: OutputFormat,
+
+    /// Keep relative paths and names of input files in output folder
+    #[arg(long)]
+    mirror_input_paths: bool,
+
+    /// Rows with null texts are dropped, processed as empty texts or kept without deduplication
+    #[arg(long, value_enum, default_value = "keep")]
+    null_policy: NullPolicy,
+
+    /// Replace invalid utf-8 in binary text columns instead of failing
+    #[arg(long)]
+    lossy_binary: bool,
 }
 
 fn clear(cli: &DeduplicateArgs) -> Result<()> {
@@ -69,31 +216,254 @@ fn deduplicate_main(cli: DeduplicateArgs) -> Result<()> {
     if cli.clear {
         clear(&cli)?;
     }
+    let mut report = RunReport::new();
 
     let context = Context::new(cli.input, cli.input_pattern, cli.tmp)?;
 
+    if !(cli.threshold > 0.0 && cli.threshold < 1.0) {
+        return Err(anyhow!("--threshold must be in range (0, 1)"));
+    }
+    if cli.num_perm == 0 || cli.num_perm > MAX_NUM_PERM {
+        return Err(anyhow!("--num-perm must be in range [1, {}]", MAX_NUM_PERM));
+    }
+    let mode = DeduplicationMode::new(
+        cli.mode,
+        cli.exact_hash_bits.parse()?,
+        cli.min_substring_tokens,
+    );
+    if mode.is_exact() && !matches!(cli.verify, VerificationKind::None) {
+        return Err(anyhow!("--verify cannot be used in exact mode"));
+    }
+    if !matches!(
+        mode,
+        DeduplicationMode::Near | DeduplicationMode::Exact { .. }
+    ) && (!matches!(cli.verify, VerificationKind::None)
+        || !matches!(cli.filter_mode, FilterMode::Remove)
+        || cli.reference.is_some())
+    {
+        return Err(anyhow!(
+            "--verify, --filter-mode and --reference can be used only in near and exact modes"
+        ));
+    }
+    if cli.min_substring_tokens == 0 || cli.suffix_array_shard_tokens <= cli.min_substring_tokens {
+        return Err(anyhow!(
+            "--min-substring-tokens must be positive and less than --suffix-array-shard-tokens"
+        ));
+    }
+    // removed spans are offsets of unicode words in original texts
+    if matches!(mode, DeduplicationMode::Substrings { .. })
+        && (!cli.normalize.is_empty() || matches!(cli.tokenizer, TokenizerKind::Legacy))
+    {
+        return Err(anyhow!(
+            "--normalize and legacy --tokenizer cannot be used in substrings mode"
+        ));
+    }
+    if !matches!(
+        mode,
+        DeduplicationMode::Near | DeduplicationMode::Exact { .. }
+    ) && (cli.column.len() > 1 || !cli.hash_column.is_empty())
+    {
+        return Err(anyhow!(
+            "Several columns and --hash-column can be used only in near and exact modes"
+        ));
+    }
+    if !cli.column_weights.is_empty() {
+        if cli.column_weights.len() != cli.column.len() || cli.column_weights.contains(&0) {
+            return Err(anyhow!(
+                "--column-weights must have a positive weight for every column"
+            ));
+        }
+        // lsh candidates are similar in at least one column, so they must be verified
+        if !matches!(cli.verify, VerificationKind::Minhash) || mode.is_exact() {
+            return Err(anyhow!(
+                "--column-weights can be used only in near mode with --verify minhash"
+            ));
+        }
+    }
+    if cli.shingle_size == Some(0) {
+        return Err(anyhow!("--shingle-size must be positive"));
+    }
+
+    let survivor_policy = SurvivorPolicy::new(
+        cli.survivor_policy,
+        cli.survivor_column,
+        cli.source_priority,
+    )?;
+    // jsonl files are read only by json paths of text and hash columns
+    if let Some(column) = survivor_policy.column() {
+        if context.input_files().iter().any(|x| is_jsonl(x)) {
+            return Err(anyhow!(
+                "--survivor-column {} cannot be used with jsonl input files",
+                column
+            ));
+        }
+    }
+    let banding = LshBanding::optimal(
+        cli.threshold,
+        cli.num_perm,
+        cli.false_positive_weight,
+        cli.false_negative_weight,
+    );
+    info!(
+        "Selected lsh banding for threshold {}: bands = {}, rows = {}",
+        cli.threshold,
+        banding.bands(),
+        banding.rows()
+    );
+    let lsh_buckets_params = LshBucketsParams::new(
+        TextColumns::new(
+            cli.column.clone(),
+            if cli.column_weights.is_empty() {
+                ColumnsCombination::Join {
+                    separator: cli.column_separator.clone(),
+                }
+            } else {
+                ColumnsCombination::Weighted {
+                    weights: cli.column_weights.clone(),
+                }
+            },
+            cli.hash_column.clone(),
+            cli.null_policy,
+            cli.lossy_binary,
+        ),
+        survivor_policy,
+        MinHashParams::new(
+            cli.num_perm,
+            Shingling::new(cli.shingle, cli.shingle_size),
+            Tokenizer::new(cli.tokenizer),
+            cli.min_tokens,
+        ),
+        banding,
+        Normalization::new(cli.normalize),
+        mode,
+    );
+    let context = match cli.reference {
+        Some(reference) => {
+            operations::load_reference(context, reference, Some(&lsh_buckets_params))?
+        }
+        None => context,
+    };
+    let run_meta = RunMeta::new(
+        context.input_files_hash(),
+        lsh_buckets_params,
+        Verification::new(cli.verify, cli.verify_threshold.unwrap_or(cli.threshold)),
+    );
+    operations::update_run_meta(&context, &run_meta)?;
+    let output = Output::new(
+        &cli.out,
+        cli.output_format,
+        cli.mirror_input_paths
+            .then_some(context.input_folder().as_str()),
+    );
+
+    if let DeduplicationMode::Substrings { min_tokens } = mode {
+        let num_shards = report.stage("suffix_arrays", || {
+            substrings::build_suffix_arrays(
+                &context,
+                run_meta.lsh_buckets_params(),
+                min_tokens as usize,
+                cli.suffix_array_shard_tokens,
+            )
+        })?;
+        report.stage("duplicated_ranges", || {
+            substrings::find_duplicated_ranges(&context, num_shards, min_tokens as usize)
+        })?;
+
+        create_dir_all(&cli.out)?;
+        report.stage("removing_ranges", || {
+            substrings::remove_duplicated_ranges(
+                &context,
+                run_meta.lsh_buckets_params(),
+                &output,
+                cli.n_workers,
+            )
+        })?;
+        output.write_manifest()?;
+        return report.write(&context, &run_meta, &output);
+    }
+
+    if mode.segmentation().is_some() {
+        report.stage("lsh_rows", || {
+            operations::process_parquet_files_from_folder_to_lsh_buckets_files(
+                &context,
+                run_meta.lsh_buckets_params(),
+                cli.lsh_buckets_size_limit,
+                cli.n_workers,
+            )
+        })?;
+        let frequent = report.stage("frequent_segments", || {
+            segments::find_frequent_segments(&context, cli.max_segment_frequency)
+        })?;
+
+        create_dir_all(&cli.out)?;
+        report.stage("stripping_segments", || {
+            segments::strip_frequent_segments(
+                &context,
+                run_meta.lsh_buckets_params(),
+                frequent,
+                &output,
+                cli.n_workers,
+            )
+        })?;
+        output.write_manifest()?;
+        return report.write(&context, &run_meta, &output);
+    }
+
     if !Path::new(&context.duplicats_groups_path()).exists() {
-        operations::process_parquet_files_from_folder_to_lsh_buckets_files(
-            &context,
-            &cli.column,
-            cli.lsh_buckets_size_limit,
-            cli.n_workers,
-        )?;
+        report.stage("lsh_rows", || {
+            operations::process_parquet_files_from_folder_to_lsh_buckets_files(
+                &context,
+                run_meta.lsh_buckets_params(),
+                cli.lsh_buckets_size_limit,
+                cli.n_workers,
+            )
+        })?;
 
-        operations::find_duplicates_in_lsh_buckets_files(
-            &context.raw_lsh_buckets_folder_path(),
-            &context.duplicats_groups_path(),
-        )?;
+        report.stage("duplicates_groups", || {
+            operations::find_duplicates_in_lsh_buckets_files(
+                &context,
+                run_meta.lsh_buckets_params().mode(),
+            )
+        })?;
+
+        if Path::new(&context.duplicates_clusters_path()).exists() {
+            remove_file(context.duplicates_clusters_path())?;
+        }
     } else {
         info!("Found duplicates.groups file, so initial processing lsh index files is skipped");
     }
 
-    operations::build_filters(&context)?;
+    if !Path::new(&context.duplicates_clusters_path()).exists() {
+        let verification = report.stage("clusters", || {
+            operations::find_clusters_in_duplicates_groups(
+                &context,
+                run_meta.lsh_buckets_params(),
+                run_meta.verification(),
+                cli.n_workers,
+            )
+        })?;
+        report.set_verification(verification);
+    } else {
+        info!("Found duplicates.clusters file, so clustering of duplicates groups is skipped");
+    }
+    report.set_num_groups(operations::count_duplicates_groups(&context)?);
+    report.set_clusters(operations::read_clusters_stats(&context)?);
+
+    report.stage("filters", || operations::build_filters(&context))?;
 
     create_dir_all(&cli.out)?;
-    operations::apply_filters(&context, &cli.column, &cli.out, cli.n_workers)?;
+    report.stage("applying_filters", || {
+        operations::apply_filters(
+            &context,
+            run_meta.lsh_buckets_params(),
+            cli.filter_mode,
+            &output,
+            cli.n_workers,
+        )
+    })?;
+    output.write_manifest()?;
 
-    Ok(())
+    report.write(&context, &run_meta, &output)
 }
 
 #[derive(clap::Args)]
@@ -108,16 +478,284 @@ struct DiffArgs {
     #[arg(long, value_name = "TMP")]
     tmp: String,
 
-    #[arg(long, value_name = "COLUMN", default_value = "content")]
-    column: String,
+    /// Text columns, texts of several columns are joined by new lines
+    #[arg(
+        long,
+        value_name = "COLUMN",
+        value_delimiter = ',',
+        default_value = "content"
+    )]
+    column: Vec<String>,
 
+    /// Number of shown clusters
     #[arg(long, value_name = "LIMIT", default_value = "100")]
     limit: usize,
+
+    /// Shown clusters are the first ones, random ones, random ones stratified by size
+    /// or the largest ones
+    #[arg(long, value_enum, default_value = "first")]
+    sample: DiffSampling,
+
+    /// Seed of random sampling
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Duplicates with lower estimated similarity to the survivor are not shown
+    #[arg(long, default_value = "0.0")]
+    min_similarity: f64,
+
+    /// Duplicates with higher estimated similarity to the survivor are not shown
+    #[arg(long, default_value = "1.0")]
+    max_similarity: f64,
+
+    /// Lsh rows folder of reference corpus used in deduplication
+    #[arg(long, value_name = "REFERENCE")]
+    reference: Option<String>,
+
+    #[arg(long, value_name = "N_WORKERS", default_value = "1")]
+    n_workers: usize,
+
+    /// Html file with side by side diffs instead of printing them
+    #[arg(long, value_name = "HTML")]
+    html: Option<String>,
+
+    /// Browse clusters in terminal, clusters marked as false positives are never deduplicated
+    #[arg(long, conflicts_with = "html")]
+    tui: bool,
 }
 
 fn diff_main(cli: DiffArgs) -> Result<()> {
     let context = Context::new(cli.input, cli.input_pattern, cli.tmp)?;
-    operations::show_diff(&context, &cli.column, cli.limit)
+    let context = match cli.reference {
+        Some(reference) => operations::load_reference(context, reference, None)?,
+        None => context,
+    };
+    let run_meta = RunMeta::read(&context.run_meta_path())?.ok_or(anyhow!(
+        "Cannot find run meta in tmp, deduplication must be run first"
+    ))?;
+    // clusters refer to documents by indices of input files of the run
+    if context.input_files_hash() != run_meta.input_files_hash() {
+        return Err(anyhow!(
+            "Input files or reference differ from deduplication run in tmp"
+        ));
+    }
+    if !Path::new(&context.duplicates_clusters_path()).exists() {
+        return Err(anyhow!(
+            "Cannot find duplicates clusters in tmp, deduplication must be run in near or exact mode first"
+        ));
+    }
+    if !(0.0..=cli.max_similarity).contains(&cli.min_similarity) || cli.max_similarity > 1.0 {
+        return Err(anyhow!(
+            "--min-similarity and --max-similarity must be in range [0, 1] and ordered"
+        ));
+    }
+    // texts are only shown, so invalid utf-8 is replaced
+    let column = TextColumns::new(
+        cli.column,
+        ColumnsCombination::Join {
+            separator: "\n".to_string(),
+        },
+        Vec::new(),
+        NullPolicy::Keep,
+        true,
+    );
+    let options = DiffOptions {
+        sampling: cli.sample,
+        limit: cli.limit,
+        min_similarity: cli.min_similarity,
+        max_similarity: cli.max_similarity,
+        seed: cli.seed,
+    };
+    if cli.tui {
+        let clusters =
+            operations::read_diff_clusters(&context, &run_meta, &column, &options, cli.n_workers)?;
+        return tui::browse_clusters(&context, clusters);
+    }
+    operations::show_diff(
+        &context,
+        &run_meta,
+        &column,
+        &options,
+        cli.html.as_deref(),
+        cli.n_workers,
+    )
+}
+
+#[derive(clap::Args)]
+#[command(version, about, long_about = None)]
+struct ContaminationArgs {
+    #[arg(long, value_name = "INPUT")]
+    input: String,
+
+    #[arg(long, value_name = "INPUT_PATTERN", default_value = "*.parquet.zst")]
+    input_pattern: String,
+
+    /// Tmp folder of deduplication run, its params and lsh rows are reused
+    #[arg(long, value_name = "TMP")]
+    tmp: String,
+
+    #[arg(long, value_name = "N_WORKERS", default_value = "1")]
+    n_workers: usize,
+
+    #[arg(long, default_value = "1073741824")]
+    lsh_buckets_size_limit: u64,
+
+    /// Evaluation sets in parquet or jsonl files
+    #[arg(long, value_name = "BENCHMARK", required = true)]
+    benchmark: Vec<String>,
+
+    #[arg(long, value_name = "BENCHMARK_COLUMN", default_value = "content")]
+    benchmark_column: String,
+
+    /// Column with ids of benchmark documents, path and row index by default
+    #[arg(long, value_name = "BENCHMARK_ID_COLUMN")]
+    benchmark_id_column: Option<String>,
+
+    /// Benchmark documents are probed by windows of the given number of words
+    #[arg(long, value_name = "WINDOW")]
+    window: Option<usize>,
+
+    /// Colliding documents with lower estimated similarity are not reported
+    #[arg(long, default_value = "0.0")]
+    min_similarity: f64,
+
+    /// Parquet file with contaminated documents, matched benchmark ids and similarities
+    #[arg(long, value_name = "REPORT")]
+    report: String,
+
+    /// Output folder for corpus without contaminated documents, nothing is removed if not set
+    #[arg(long, value_name = "OUT")]
+    out: Option<String>,
+
+    /// Format of output files, jsonl files are always written as jsonl
+    #[arg(long, value_enum, default_value = "auto")]
+    output_format: OutputFormat,
+
+    /// Keep relative paths and names of input files in output folder
+    #[arg(long)]
+    mirror_input_paths: bool,
+}
+
+fn contamination_main(cli: ContaminationArgs) -> Result<()> {
+    let context = Context::new(
+        cli.input.clone(),
+        cli.input_pattern.clone(),
+        cli.tmp.clone(),
+    )?;
+    let run_meta = RunMeta::read(&context.run_meta_path())?.ok_or(anyhow!(
+        "Cannot find run meta in tmp, deduplication must be run first"
+    ))?;
+    // lsh rows of other input files are built in a subfolder,
+    // so rows of the deduplication run are not replaced
+    let context = if context.input_files_hash() == run_meta.input_files_hash() {
+        context
+    } else {
+        let tmp = format!("{}/contamination", cli.tmp);
+        info!(
+            "Input files differ from deduplication run, lsh rows are built in {}",
+            tmp
+        );
+        Context::new(cli.input, cli.input_pattern, tmp)?
+    };
+    let params = run_meta.lsh_buckets_params();
+    if *params.mode() != DeduplicationMode::Near {
+        return Err(anyhow!(
+            "Contamination can be found only with near mode lsh rows"
+        ));
+    }
+    if params.text_columns().weights().is_some() {
+        return Err(anyhow!(
+            "Contamination cannot be found with weighted columns lsh rows"
+        ));
+    }
+    if cli.window == Some(0) {
+        return Err(anyhow!("--window must be positive"));
+    }
+
+    operations::process_parquet_files_from_folder_to_lsh_buckets_files(
+        &context,
+        params,
+        cli.lsh_buckets_size_limit,
+        cli.n_workers,
+    )?;
+
+    let documents = contamination::read_benchmark(
+        &cli.benchmark,
+        &cli.benchmark_column,
+        cli.benchmark_id_column.as_deref(),
+    )?;
+    let matches = contamination::find_contamination(
+        &context,
+        params,
+        &documents,
+        cli.window,
+        cli.min_similarity,
+        cli.n_workers,
+    )?;
+    contamination::write_report(&context, &matches, &cli.report)?;
+
+    if let Some(out) = cli.out {
+        create_dir_all(&out)?;
+        let output = Output::new(
+            &out,
+            cli.output_format,
+            cli.mirror_input_paths
+                .then_some(context.input_folder().as_str()),
+        );
+        contamination::remove_contamination(
+            &context,
+            params.text_columns(),
+            &matches,
+            &output,
+            cli.n_workers,
+        )?;
+        output.write_manifest()?;
+    }
+
+    Ok(())
+}
+
+#[derive(clap::Args)]
+#[command(version, about, long_about = None)]
+struct ExportClustersArgs {
+    #[arg(long, value_name = "INPUT")]
+    input: String,
+
+    #[arg(long, value_name = "INPUT_PATTERN", default_value = "*.parquet.zst")]
+    input_pattern: String,
+
+    /// Tmp folder of near or exact deduplication run
+    #[arg(long, value_name = "TMP")]
+    tmp: String,
+
+    /// Lsh rows folder of reference corpus used in deduplication
+    #[arg(long, value_name = "REFERENCE")]
+    reference: Option<String>,
+
+    #[arg(long, value_name = "N_WORKERS", default_value = "1")]
+    n_workers: usize,
+
+    /// Parquet file with one row per member of every duplicates cluster
+    #[arg(long, value_name = "OUT")]
+    out: String,
+}
+
+fn export_clusters_main(cli: ExportClustersArgs) -> Result<()> {
+    let context = Context::new(cli.input, cli.input_pattern, cli.tmp)?;
+    let context = match cli.reference {
+        Some(reference) => operations::load_reference(context, reference, None)?,
+        None => context,
+    };
+    let run_meta = RunMeta::read(&context.run_meta_path())?.ok_or(anyhow!(
+        "Cannot find run meta in tmp, deduplication must be run first"
+    ))?;
+    // clusters refer to documents by indices of input files of the run
+    if context.input_files_hash() != run_meta.input_files_hash() {
+        return Err(anyhow!(
+            "Input files or reference differ from deduplication run in tmp"
+        ));
+    }
+    operations::export_clusters(&context, &run_meta, &cli.out, cli.n_workers)
 }
 
 fn main() -> Result<()> {
@@ -127,5 +765,7 @@ fn main() -> Result<()> {
     match cli {
         Cli::Deduplicate(args) => deduplicate_main(args),
         Cli::Diff(args) => diff_main(args),
+        Cli::Contamination(args) => contamination_main(args),
+        Cli::ExportClusters(args) => export_clusters_main(args),
     }
 }
@@ -1,29 +1,113 @@
+use crate::{
+    batch_io::HASH_COLUMNS_SEPARATOR,
+    tokenizer::{split_spaceless_words, Tokenizer},
+};
 use cityhasher::hash;
 use lazy_static::lazy_static;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
-use regex::Regex;
+use serde::Serialize;
+use speedy::{Readable, Writable};
 use std::cmp;
 use std::iter::zip;
 
+pub const MAX_NUM_PERM: usize = 1024;
+
 lazy_static! {
-    static ref NUM_PERM: usize = 256;
     static ref PERMUTATIONS: (Vec<u32>, Vec<u32>) = {
         let mut gen = ChaCha8Rng::seed_from_u64(1);
         let mut permutations = (Vec::new(), Vec::new());
-        for _ in 0..*NUM_PERM {
+        for _ in 0..MAX_NUM_PERM {
             permutations.0.push(gen.gen_range(1..u32::MAX));
-            permutations.0.push(gen.gen_range(0..u32::MAX));
+            permutations.1.push(gen.gen_range(0..u32::MAX));
         }
         permutations
     };
-    static ref TEXT_SPLITTER: Regex = Regex::new("[^А-Яа-яёЁA-Za-z_0-9]+").unwrap();
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ShinglingKind {
+    Word,
+    Char,
+}
+
+// Defines elements of the set which Jaccard similarity is computed on
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Shingling {
+    // Word n-grams of the given size
+    Words { size: u32 },
+    // Character k-grams of the given size over words joined by spaces
+    Chars { size: u32 },
+}
+
+impl Shingling {
+    pub fn new(kind: ShinglingKind, size: Option<usize>) -> Self {
+        match kind {
+            ShinglingKind::Word => Shingling::Words {
+                size: size.unwrap_or(1) as u32,
+            },
+            ShinglingKind::Char => Shingling::Chars {
+                size: size.unwrap_or(5) as u32,
+            },
+        }
+    }
+}
+
+// Parameters of minhash signatures, signatures are comparable only
+// if they are built with the same parameters
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct MinHashParams {
+    num_perm: u32,
+    shingling: Shingling,
+    tokenizer: Tokenizer,
+    // documents with less tokens have no signature and are never considered duplicates
+    min_tokens: u32,
+}
+
+impl MinHashParams {
+    pub fn new(
+        num_perm: usize,
+        shingling: Shingling,
+        tokenizer: Tokenizer,
+        min_tokens: usize,
+    ) -> Self {
+        assert!(num_perm > 0 && num_perm <= MAX_NUM_PERM);
+        Self {
+            num_perm: num_perm as u32,
+            shingling,
+            tokenizer,
+            min_tokens: min_tokens as u32,
+        }
+    }
+
+    pub fn num_perm(&self) -> usize {
+        self.num_perm as usize
+    }
+
+    pub fn shingling(&self) -> &Shingling {
+        &self.shingling
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
 }
 
 pub struct MinHash {
     values: Vec<u32>,
 }
 
+impl MinHash {
+    // Jaccard similarity estimated as the fraction of equal minimums
+    pub fn jaccard(&self, other: &MinHash) -> f64 {
+        let equal = zip(&self.values, &other.values)
+            .filter(|(x, y)| x == y)
+            .count();
+        equal as f64 / self.values.len() as f64
+    }
+}
+
 impl<Idx> std::ops::Index<Idx> for MinHash
 where
     Idx: std::slice::SliceIndex<[u32]>,
@@ -41,15 +125,14 @@ struct MinHashBuilder {
 }
 
 impl MinHashBuilder {
-    pub fn new() -> Self {
+    pub fn new(num_perm: usize) -> Self {
         Self {
-            buffer: vec![0; *NUM_PERM],
-            values: vec![u32::MAX; *NUM_PERM],
+            buffer: vec![0; num_perm],
+            values: vec![u32::MAX; num_perm],
         }
     }
 
-    pub fn update(&mut self, value: &str) {
-        let hash = hash::<u32>(&value);
+    pub fn update(&mut self, hash: u32) {
         for (x, y) in zip(&mut self.buffer, &PERMUTATIONS.0) {
             *x = y.wrapping_mul(hash);
         }
@@ -68,16 +151,249 @@ impl MinHashBuilder {
     }
 }
 
-pub fn hash_text(text: &str) -> MinHash {
-    let mut builder = MinHashBuilder::new();
-    let lowercase = text.to_lowercase();
+fn word_shingles(tokens: &[String], size: usize) -> Vec<u32> {
+    if tokens.len() <= size {
+        return if tokens.is_empty() {
+            Vec::new()
+        } else {
+            vec![hash::<u32>(tokens.join(" "))]
+        };
+    }
+    tokens
+        .windows(size)
+        .map(|window| hash::<u32>(window.join(" ")))
+        .collect()
+}
+
+fn char_shingles(words: &[String], size: usize) -> Vec<u32> {
+    let joined = words.join(" ");
+    let chars: Vec<(usize, char)> = joined.char_indices().collect();
+    if chars.len() <= size {
+        return if chars.is_empty() {
+            Vec::new()
+        } else {
+            vec![hash::<u32>(&joined)]
+        };
+    }
+    (0..=(chars.len() - size))
+        .map(|start| {
+            let begin = chars[start].0;
+            let end = chars.get(start + size).map_or(joined.len(), |x| x.0);
+            hash::<u32>(&joined[begin..end])
+        })
+        .collect()
+}
+
+// Hashes of text shingles and number of tokens
+fn shingles_and_tokens(text: &str, params: &MinHashParams) -> (Vec<u32>, usize) {
+    let words = params.tokenizer.words(text);
+    let tokens = split_spaceless_words(&words);
+    let mut result = match *params.shingling() {
+        Shingling::Words { size } => word_shingles(&tokens, size as usize),
+        Shingling::Chars { size } => char_shingles(&words, size as usize),
+    };
+    result.sort();
+    result.dedup();
+    (result, tokens.len())
+}
 
-    for token in TEXT_SPLITTER.split(&lowercase) {
-        if token.is_empty() {
+// Hashes of text shingles, sorted and without repetitions,
+// empty for documents shorter than min_tokens
+pub fn text_shingles(text: &str, params: &MinHashParams) -> Vec<u32> {
+    let (shingles, num_tokens) = shingles_and_tokens(text, params);
+    if num_tokens == 0 || num_tokens < params.min_tokens as usize {
+        return Vec::new();
+    }
+    shingles
+}
+
+// Exact Jaccard similarity of two shingles sets built by text_shingles
+pub fn shingles_jaccard(a: &[u32], b: &[u32]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let (mut i, mut j, mut intersection) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            cmp::Ordering::Less => i += 1,
+            cmp::Ordering::Greater => j += 1,
+            cmp::Ordering::Equal => {
+                intersection += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    intersection as f64 / (a.len() + b.len() - intersection) as f64
+}
+
+// Signature of the text, none for documents without shingles,
+// otherwise all of them would have the same signature of u32::MAX values
+pub fn hash_text(text: &str, params: &MinHashParams) -> Option<MinHash> {
+    let shingles = text_shingles(text, params);
+    if shingles.is_empty() {
+        return None;
+    }
+
+    let mut builder = MinHashBuilder::new(params.num_perm());
+    for shingle in shingles {
+        builder.update(shingle);
+    }
+
+    Some(builder.build())
+}
+
+// Columns of permutations, every column gets a contiguous share by its weight,
+// so lsh bands mostly fall into one column and similar columns make candidates
+fn weighted_columns(weights: &[u32], num_perm: usize) -> Vec<usize> {
+    let total: u64 = weights.iter().map(|x| *x as u64).sum();
+    let mut bounds = Vec::with_capacity(weights.len());
+    let mut cumulative = 0u64;
+    for weight in weights {
+        cumulative += *weight as u64;
+        bounds.push(cumulative * num_perm as u64);
+    }
+    (0..num_perm)
+        .map(|i| bounds.iter().position(|x| *x > i as u64 * total).unwrap())
+        .collect()
+}
+
+// Signature of texts of several columns, every permutation takes the minimum of one column,
+// so similarity is the weighted mean of similarities of columns, lsh candidates are similar
+// in at least one column, min_tokens is applied to all texts together
+pub fn hash_weighted_texts(
+    texts: &[&str],
+    weights: &[u32],
+    params: &MinHashParams,
+) -> Option<MinHash> {
+    let mut num_tokens = 0;
+    let mut minhashes = Vec::with_capacity(texts.len());
+    for text in texts {
+        let (shingles, text_tokens) = shingles_and_tokens(text, params);
+        num_tokens += text_tokens;
+        if shingles.is_empty() {
+            minhashes.push(None);
             continue;
         }
-        builder.update(token);
+        let mut builder = MinHashBuilder::new(params.num_perm());
+        for shingle in shingles {
+            builder.update(shingle);
+        }
+        minhashes.push(Some(builder.build()));
+    }
+    if num_tokens == 0 || num_tokens < params.min_tokens as usize {
+        return None;
+    }
+
+    // permutations of empty columns get values of the whole document, otherwise they
+    // would be u32::MAX, so all documents with the empty column would collide in their bands
+    let document_hash = hash::<u32>(texts.join(HASH_COLUMNS_SEPARATOR));
+    let values = weighted_columns(weights, params.num_perm())
+        .into_iter()
+        .enumerate()
+        .map(|(i, column)| match &minhashes[column] {
+            Some(minhash) => minhash[i],
+            None => hash::<u32>(format!("{}{}", i, document_hash)),
+        })
+        .collect();
+    Some(MinHash { values })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::TokenizerKind;
+
+    fn params() -> MinHashParams {
+        MinHashParams::new(
+            128,
+            Shingling::new(ShinglingKind::Word, None),
+            Tokenizer::new(TokenizerKind::Unicode),
+            1,
+        )
+    }
+
+    #[test]
+    fn weighted_columns_are_contiguous_shares() {
+        let columns = weighted_columns(&[1, 3], 8);
+        assert_eq!(columns, vec![0, 0, 1, 1, 1, 1, 1, 1]);
+        let columns = weighted_columns(&[1, 1, 1], 128);
+        assert!(columns.windows(2).all(|x| x[0] <= x[1]));
+        for column in 0..3 {
+            let share = columns.iter().filter(|x| **x == column).count();
+            assert!((42..=43).contains(&share));
+        }
+    }
+
+    #[test]
+    fn empty_weighted_column_does_not_match() {
+        let params = params();
+        let a = hash_weighted_texts(&["first distinct text here", ""], &[1, 1], &params).unwrap();
+        let b = hash_weighted_texts(&["other words entirely now", ""], &[1, 1], &params).unwrap();
+        assert!(a.jaccard(&b) < 0.1);
+
+        let c = hash_weighted_texts(&["first distinct text here", ""], &[1, 1], &params).unwrap();
+        assert_eq!(a.jaccard(&c), 1.0);
+    }
+
+    #[test]
+    fn weighted_similarity_is_mean_of_columns() {
+        let params = params();
+        let a = hash_weighted_texts(&["same question text", "answer one"], &[1, 1], &params);
+        let b = hash_weighted_texts(&["same question text", "reply two"], &[1, 1], &params);
+        let similarity = a.unwrap().jaccard(&b.unwrap());
+        assert!((0.4..=0.6).contains(&similarity));
+    }
+
+    #[test]
+    fn every_permutation_has_offset() {
+        assert_eq!(PERMUTATIONS.0.len(), MAX_NUM_PERM);
+        assert_eq!(PERMUTATIONS.1.len(), MAX_NUM_PERM);
+    }
+
+    #[test]
+    fn estimate_follows_jaccard_similarity() {
+        let params = MinHashParams::new(
+            MAX_NUM_PERM,
+            Shingling::new(ShinglingKind::Word, None),
+            Tokenizer::new(TokenizerKind::Unicode),
+            1,
+        );
+        let words = |range: std::ops::Range<usize>| {
+            range
+                .map(|x| format!("w{}", x))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let a = hash_text(&words(0..100), &params).unwrap();
+        for (range, jaccard) in [(0..100, 1.0), (50..150, 1.0 / 3.0), (20..120, 80.0 / 120.0)] {
+            let b = hash_text(&words(range), &params).unwrap();
+            assert!((a.jaccard(&b) - jaccard).abs() < 0.05);
+        }
+        let b = hash_text(&words(100..200), &params).unwrap();
+        assert!(a.jaccard(&b) < 0.02);
+    }
+
+    #[test]
+    fn documents_shorter_than_min_tokens_have_no_signature() {
+        let params = MinHashParams::new(
+            128,
+            Shingling::new(ShinglingKind::Word, None),
+            Tokenizer::new(TokenizerKind::Unicode),
+            4,
+        );
+        assert!(hash_text("one two three", &params).is_none());
+        assert!(hash_text("one two three four", &params).is_some());
+        // spaceless scripts are counted by character bigrams
+        assert!(hash_text("北京天安", &params).is_none());
+        assert!(hash_text("北京天安门", &params).is_some());
+        assert!(text_shingles("one two three", &params).is_empty());
     }
 
-    builder.build()
+    #[test]
+    fn documents_without_tokens_have_no_signature() {
+        let params = params();
+        assert!(hash_text("", &params).is_none());
+        assert!(hash_weighted_texts(&["", ""], &[1, 1], &params).is_none());
+    }
 }
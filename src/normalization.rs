@@ -0,0 +1,75 @@
+use html_escape::decode_html_entities;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use speedy::{Readable, Writable};
+use std::borrow::Cow;
+use unicode_normalization::UnicodeNormalization;
+
+lazy_static! {
+    static ref HTML_SCRIPT: Regex = Regex::new("(?is)<script[^>]*>.*?</script>").unwrap();
+    static ref HTML_STYLE: Regex = Regex::new("(?is)<style[^>]*>.*?</style>").unwrap();
+    static ref HTML_TAG: Regex = Regex::new("(?s)<[^>]*>").unwrap();
+    static ref WHITESPACE: Regex = Regex::new(r"\s+").unwrap();
+}
+
+#[derive(clap::ValueEnum, Readable, Writable, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizationStep {
+    // Removes tags, scripts and styles, decodes entities
+    Html,
+    Nfc,
+    Nfkc,
+    Lowercase,
+    // Collapses whitespace runs into a single space
+    Whitespace,
+    // Removes everything except letters, digits and whitespace
+    Punctuation,
+    // Replaces every digit with 0
+    Digits,
+}
+
+impl NormalizationStep {
+    fn apply(&self, text: &str) -> String {
+        match self {
+            NormalizationStep::Html => {
+                let text = HTML_SCRIPT.replace_all(text, " ");
+                let text = HTML_STYLE.replace_all(&text, " ");
+                let text = HTML_TAG.replace_all(&text, " ");
+                decode_html_entities(&text).into_owned()
+            }
+            NormalizationStep::Nfc => text.nfc().collect(),
+            NormalizationStep::Nfkc => text.nfkc().collect(),
+            NormalizationStep::Lowercase => text.to_lowercase(),
+            NormalizationStep::Whitespace => WHITESPACE.replace_all(text, " ").trim().to_string(),
+            NormalizationStep::Punctuation => text
+                .chars()
+                .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+                .collect(),
+            NormalizationStep::Digits => text
+                .chars()
+                .map(|c| if c.is_numeric() { '0' } else { c })
+                .collect(),
+        }
+    }
+}
+
+// Steps applied in the given order to texts before content hashing and minhashing
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct Normalization {
+    steps: Vec<NormalizationStep>,
+}
+
+impl Normalization {
+    pub fn new(steps: Vec<NormalizationStep>) -> Self {
+        Self { steps }
+    }
+
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut result = Cow::Borrowed(text);
+        for step in &self.steps {
+            result = Cow::Owned(step.apply(&result));
+        }
+        result
+    }
+}
@@ -1,69 +1,202 @@
-use crate::{context::Context, diff, lsh::LshBucketsMeta};
-use anyhow::Result;
-use cityhasher::hash;
+use crate::{
+    context::{Context, DocId},
+    diff::{self, DiffCluster, DiffMember, DiffSampling},
+    lsh::{LshBucketsMeta, LshBucketsParams},
+    run_meta::RunMeta,
+};
+use anyhow::{anyhow, Result};
+use arrow::{
+    compute::filter_record_batch,
+    datatypes::{DataType, Field, FieldRef, Schema, SchemaRef},
+};
+use arrow_array::{BooleanArray, Float64Array, RecordBatch, StringArray, UInt32Array, UInt64Array};
+use cityhasher::{hash, hash_with_seed};
 use indicatif::ProgressBar;
 use log::{debug, error, info, warn};
-use rand::{prelude::SliceRandom, thread_rng};
+use rand::{prelude::SliceRandom, thread_rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use speedy::{IsEof, Readable, Writable};
 use std::{
+    borrow::Cow,
     cmp,
-    collections::{HashMap, HashSet},
-    fs::{canonicalize, create_dir_all, read_dir, remove_file, File},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, File},
     io::{BufWriter, Write},
+    iter,
     path::Path,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        mpsc, Arc,
     },
 };
 use threadpool::ThreadPool;
 use zstd::stream;
 
 use crate::lsh::{
-    create_lsh_buckets, LshBucketRow, LshBucketRowsFilesMerger, LshBucketRowsFilesWriter,
+    create_lsh_buckets, DeduplicationMode, LshBucketRow, LshBucketRowsFilesMerger,
+    LshBucketRowsFilesWriter,
 };
 use crate::{
-    minhash::hash_text,
-    parquet_io::{ParquetReader, ParquetWriter},
+    batch_io::{BatchReader, BatchWriter, Output, TextColumns, TextReader, HASH_COLUMNS_SEPARATOR},
+    minhash::{hash_text, hash_weighted_texts, text_shingles, MinHash},
+    union_find::UnionFind,
+    verification::{self, Signature, Verification},
 };
 
-fn parquet_file_to_lsh_rows(path: &String, column_name: &String) -> Result<Vec<LshBucketRow>> {
-    let path = canonicalize(path)?.display().to_string();
-    debug!("Started processing file to lsh rows, file: {}", path);
+const EXACT_HASH_SEED: u64 = 0x9e3779b97f4a7c15;
 
-    let path_hash = Context::hash_path(&path);
+// Waits for workers, they log their errors and panic, so the run fails if any of them did
+pub fn join_workers(pool: &ThreadPool) -> Result<()> {
+    pool.join();
+    if pool.panic_count() > 0 {
+        return Err(anyhow!("{} workers failed", pool.panic_count()));
+    }
+    Ok(())
+}
+
+// Minhash of the document, texts of columns are minhashed separately if they are weighted
+fn document_minhash(
+    text: &str,
+    parts: Option<&Vec<String>>,
+    params: &LshBucketsParams,
+) -> Option<MinHash> {
+    match (params.text_columns().weights(), parts) {
+        (Some(weights), Some(parts)) => {
+            let parts: Vec<Cow<str>> = parts
+                .iter()
+                .map(|x| params.normalization().apply(x))
+                .collect();
+            let parts: Vec<&str> = parts.iter().map(|x| x.as_ref()).collect();
+            hash_weighted_texts(&parts, weights, params.minhash_params())
+        }
+        _ => hash_text(&params.normalization().apply(text), params.minhash_params()),
+    }
+}
+
+// Texts of text columns if they are weighted
+fn read_weighted_parts(
+    record_batch: &RecordBatch,
+    path: &str,
+    params: &LshBucketsParams,
+) -> Result<Option<Vec<Option<Vec<String>>>>> {
+    if params.text_columns().weights().is_none() {
+        return Ok(None);
+    }
+    Ok(Some(params.text_columns().read_parts(record_batch, path)?))
+}
+
+fn parquet_file_to_lsh_rows(
+    path: &str,
+    file_index: u32,
+    params: &LshBucketsParams,
+) -> Result<Vec<LshBucketRow>> {
+    debug!("Started processing file to lsh rows, file: {}", path);
 
     let mut result = Vec::new();
+    let mut num_skipped = 0u64;
+    let mut row_index = 0u32;
+
+    let mut parquet_reader = BatchReader::try_new(path, params.text_columns())?;
+    while let Some((record_batch, texts)) = parquet_reader.next()? {
+        let ranks = params
+            .survivor_policy()
+            .rank_batch(path, &record_batch, &texts)?;
+        let parts = read_weighted_parts(&record_batch, path, params)?;
+        let hash_parts = params.text_columns().read_hash_parts(&record_batch, path)?;
+        for (i, (text, rank)) in texts.iter().zip(ranks).enumerate() {
+            let doc_id = DocId {
+                file_index,
+                row_index,
+            };
+            row_index += 1;
+
+            let text = match text {
+                Some(text) => text,
+                None => {
+                    num_skipped += 1;
+                    continue;
+                }
+            };
 
-    let mut parquet_reader = ParquetReader::try_new(&path, column_name)?;
-    while parquet_reader.has_data_left()? {
-        let text = parquet_reader.next()?;
-        let content_hash = hash::<u64>(&text);
-        let minhash = hash_text(&text);
-        let lsh_buckets = create_lsh_buckets(&minhash);
-        let rows = lsh_buckets
-            .iter()
-            .map(|x| LshBucketRow::new(x.index(), x.hash(), path_hash, content_hash));
-        result.extend(rows);
+            if let Some(segmentation) = params.mode().segmentation() {
+                let rows = segmentation
+                    .segment_hashes(text, params.normalization())
+                    .into_iter()
+                    .map(|x| LshBucketRow::new(0, x, doc_id, x, rank));
+                result.extend(rows);
+                continue;
+            }
+
+            // content hash is computed over hash columns if they are set
+            let content_text = match hash_parts.as_ref().map(|x| &x[i]) {
+                Some(Some(hash_parts)) => Cow::Owned(
+                    hash_parts
+                        .iter()
+                        .map(|x| params.normalization().apply(x))
+                        .collect::<Vec<_>>()
+                        .join(HASH_COLUMNS_SEPARATOR),
+                ),
+                Some(None) => Cow::Borrowed(""),
+                None => params.normalization().apply(text),
+            };
+            let content_hash = hash::<u64>(content_text.as_ref());
+
+            if let DeduplicationMode::Exact { hash_bits } = params.mode() {
+                if content_text.is_empty() {
+                    num_skipped += 1;
+                    continue;
+                }
+                let bucket_hash = if *hash_bits == 128 {
+                    hash_with_seed::<u64>(content_text.as_ref(), EXACT_HASH_SEED)
+                } else {
+                    content_hash
+                };
+                result.push(LshBucketRow::new(
+                    0,
+                    bucket_hash,
+                    doc_id,
+                    content_hash,
+                    rank,
+                ));
+                continue;
+            }
+
+            let minhash =
+                document_minhash(text, parts.as_ref().and_then(|x| x[i].as_ref()), params);
+            if minhash.is_none() {
+                num_skipped += 1;
+                continue;
+            }
+            let minhash = minhash.unwrap();
+            let lsh_buckets = create_lsh_buckets(&minhash, params.banding());
+            let rows = lsh_buckets
+                .iter()
+                .map(|x| LshBucketRow::new(x.index(), x.hash(), doc_id, content_hash, rank));
+            result.extend(rows);
+        }
     }
 
-    debug!("Stopped processing file to lsh rows, file: {}", path);
+    debug!(
+        "Stopped processing file to lsh rows, file: {}, skipped short or null documents: {}",
+        path, num_skipped
+    );
 
     Ok(result)
 }
 
 fn process_parquet_files_to_lsh_bucket_rows_files(
-    paths: Vec<String>,
-    column_name: String,
+    files: Vec<(u32, String)>,
+    params: LshBucketsParams,
     output_folder: String,
     lsh_buckets_size_limit: u64,
     progress_bar: &ProgressBar,
 ) -> Result<()> {
-    let mut writer = LshBucketRowsFilesWriter::new(output_folder.clone(), lsh_buckets_size_limit);
+    let rows_params = params.clone();
+    let mut writer = LshBucketRowsFilesWriter::new(output_folder, params, lsh_buckets_size_limit);
 
-    for path in paths {
-        let rows = parquet_file_to_lsh_rows(&path, &column_name)?;
-        writer.write_rows(&path, &column_name, rows)?;
+    for (file_index, path) in files {
+        let rows = parquet_file_to_lsh_rows(&path, file_index, &rows_params)?;
+        writer.write_rows(&path, file_index, rows)?;
         progress_bar.inc(1);
     }
 
@@ -74,7 +207,7 @@ fn process_parquet_files_to_lsh_bucket_rows_files(
 
 pub fn process_parquet_files_from_folder_to_lsh_buckets_files(
     context: &Context,
-    column_name: &String,
+    params: &LshBucketsParams,
     lsh_buckets_size_limit: u64,
     n_workers: usize,
 ) -> Result<()> {
@@ -101,20 +234,42 @@ pub fn process_parquet_files_from_folder_to_lsh_buckets_files(
             continue;
         }
         let decoder = stream::Decoder::new(File::open(path)?)?;
-        let meta = LshBucketsMeta::read_from_stream_unbuffered(decoder)?;
-        if meta.column_name() != column_name {
+        let meta = LshBucketsMeta::read_from_stream_unbuffered(decoder);
+        if let Err(e) = &meta {
+            warn!("Cannot read {}: {}, so it is removed", path, e);
+            remove_file(path)?;
+            continue;
+        }
+        let meta = meta?;
+        if meta.params() != params {
+            warn!(
+                "Params {:?} are different with {:?}, so {} is removed",
+                meta.params(),
+                params,
+                path
+            );
+            remove_file(path)?;
+            continue;
+        }
+        let moved_file = meta
+            .files()
+            .iter()
+            .find(|x| input_files.get(x.index() as usize) != Some(x.path()));
+        if let Some(moved_file) = moved_file {
             warn!(
-                "Column name {} is different with {}, so {} is removed",
-                meta.column_name(),
-                column_name,
+                "Index of {} in input files is changed, so {} is removed",
+                moved_file.path(),
                 path
             );
             remove_file(path)?;
             continue;
         }
         for file in meta.files() {
-            debug!("{} is already processed to lsh_rows, will be skipped", file);
-            processed_input_files.insert(file.clone());
+            debug!(
+                "{} is already processed to lsh_rows, will be skipped",
+                file.path()
+            );
+            processed_input_files.insert(file.index());
         }
         known_lsh_rows_files.insert(format!("{}.lsh_rows", meta.file_prefix()));
     }
@@ -130,10 +285,11 @@ pub fn process_parquet_files_from_folder_to_lsh_buckets_files(
         }
     }
 
-    let mut input_files: Vec<String> = input_files
+    let mut input_files: Vec<(u32, String)> = input_files
         .iter()
-        .filter(|v| !processed_input_files.contains(*v))
-        .map(|v| v.clone())
+        .enumerate()
+        .map(|(index, path)| (index as u32, path.clone()))
+        .filter(|(index, _)| !processed_input_files.contains(index))
         .collect();
     input_files.shuffle(&mut thread_rng());
 
@@ -145,16 +301,16 @@ pub fn process_parquet_files_from_folder_to_lsh_buckets_files(
         let worker_files = Vec::from(
             &input_files[start..cmp::min(input_files.len(), start + num_files_per_worker)],
         );
-        let column_name = column_name.clone();
+        let params = params.clone();
         let output_folder = output_folder.clone();
         let progress_bar = progress_bar.clone();
         let worker = move || {
             let result = process_parquet_files_to_lsh_bucket_rows_files(
                 worker_files,
-                column_name,
+                params,
                 output_folder,
                 lsh_buckets_size_limit,
-                &*progress_bar,
+                &progress_bar,
             );
             if result.is_err() {
                 error!("{}", result.err().unwrap());
@@ -163,7 +319,7 @@ pub fn process_parquet_files_from_folder_to_lsh_buckets_files(
         };
         pool.execute(worker);
     }
-    pool.join();
+    join_workers(&pool)?;
 
     progress_bar.finish();
 
@@ -175,10 +331,11 @@ pub fn process_parquet_files_from_folder_to_lsh_buckets_files(
     Ok(())
 }
 
-#[derive(Readable, Writable, Debug)]
+#[derive(Readable, Writable, Debug, Clone, Copy)]
 struct DuplicatesGroupItem {
-    path_hash: u16,
+    doc_id: DocId,
     content_hash: u64,
+    rank: u64,
 }
 
 #[derive(Readable, Writable, Debug)]
@@ -186,29 +343,139 @@ struct DuplicatesGroup {
     group: Vec<DuplicatesGroupItem>,
 }
 
+// Reference is lsh rows folder of already accepted corpus, for example raw_lsh_buckets
+// of its own run, rows must be built with compatible params including text columns
+pub fn load_reference(
+    context: Context,
+    reference_folder: String,
+    params: Option<&LshBucketsParams>,
+) -> Result<Context> {
+    let mut reference_files = Vec::new();
+    let mut num_metas = 0;
+    for path in read_dir(&reference_folder)? {
+        let path = path?.path().display().to_string();
+        if !path.ends_with(".lsh_meta") {
+            continue;
+        }
+        let decoder = stream::Decoder::new(File::open(&path)?)?;
+        let meta = LshBucketsMeta::read_from_stream_unbuffered(decoder)?;
+        if params.is_some_and(|x| !x.is_compatible(meta.params())) {
+            return Err(anyhow!(
+                "Params {:?} of reference {} are not compatible with {:?}",
+                meta.params(),
+                path,
+                params.unwrap()
+            ));
+        }
+        for file in meta.files() {
+            let index = file.index() as usize;
+            if reference_files.len() <= index {
+                reference_files.resize(index + 1, String::new());
+            }
+            reference_files[index] = file.path().clone();
+        }
+        num_metas += 1;
+    }
+    if num_metas == 0 {
+        return Err(anyhow!(
+            "Cannot find lsh meta files in reference {}",
+            reference_folder
+        ));
+    }
+    info!(
+        "Loaded reference {}, files: {}",
+        reference_folder,
+        reference_files.len()
+    );
+
+    Ok(context.with_reference(reference_folder, reference_files))
+}
+
+// Removes results of stages after lsh rows building if they were built with other parameters,
+// lsh rows files are checked separately against their own meta files
+pub fn update_run_meta(context: &Context, run_meta: &RunMeta) -> Result<()> {
+    let path = context.run_meta_path();
+    let previous = RunMeta::read(&path)?;
+    if previous.as_ref().is_some_and(|x| x == run_meta) {
+        return Ok(());
+    }
+
+    if let Some(previous) = &previous {
+        warn!(
+            "Run meta {:?} is different with {:?}, so stale duplicates and filters are removed",
+            previous, run_meta
+        );
+    }
+
+    // duplicates groups depend only on lsh rows, clusters depend on everything
+    let mut stale_files = vec![context.duplicates_clusters_path()];
+    if previous.is_none_or(|x| {
+        x.lsh_buckets_params() != run_meta.lsh_buckets_params()
+            || x.input_files_hash() != run_meta.input_files_hash()
+    }) {
+        stale_files.push(context.duplicats_groups_path());
+    }
+    for path in stale_files {
+        if Path::new(&path).exists() {
+            remove_file(path)?;
+        }
+    }
+    remove_dir_all(context.filters_folder_path())?;
+    create_dir_all(context.filters_folder_path())?;
+
+    run_meta.write(&path)
+}
+
+// In exact mode rows of a group must also have equal content hashes,
+// since for 128 bits hash the bucket hash is only a half of it
 pub fn find_duplicates_in_lsh_buckets_files(
-    input_folder: &String,
-    output_file: &String,
+    context: &Context,
+    mode: &DeduplicationMode,
 ) -> Result<()> {
+    let input_folder = context.raw_lsh_buckets_folder_path();
     info!("Starting finding duplicates in folder {}", input_folder);
 
-    let mut merger = LshBucketRowsFilesMerger::new(input_folder)?;
+    let mut folders = vec![(input_folder.clone(), 0)];
+    if let Some(reference_folder) = context.reference_folder() {
+        info!("Reference lsh rows are merged from {}", reference_folder);
+        folders.push((
+            reference_folder.clone(),
+            context.reference_file_index_offset(),
+        ));
+    }
+    let mut merger = LshBucketRowsFilesMerger::new(&folders)?;
+    let output_file = context.duplicats_groups_path();
     let mut output_writer = stream::Encoder::new(BufWriter::new(File::create(output_file)?), 1)?;
 
     let mut flush = |group: &mut Vec<LshBucketRow>| -> Result<()> {
-        group.sort_by(|a, b| a.content_hash().cmp(&b.content_hash()));
-
-        let duplicates_group = DuplicatesGroup {
-            group: group
-                .iter()
-                .map(|x| DuplicatesGroupItem {
-                    path_hash: x.path_hash(),
-                    content_hash: x.content_hash(),
-                })
-                .collect(),
+        group.sort_by_key(|x| x.content_hash());
+
+        let subgroups: Vec<&[LshBucketRow]> = if mode.is_exact() {
+            group
+                .chunk_by(|a, b| a.content_hash() == b.content_hash())
+                .filter(|x| x.len() > 1)
+                .collect()
+        } else {
+            vec![group]
         };
-        //println!("{:?}", duplicates_group);
-        duplicates_group.write_to_stream(&mut output_writer)?;
+
+        for subgroup in subgroups {
+            // nothing is removed from groups of reference documents only
+            if subgroup.iter().all(|x| context.is_reference(&x.doc_id())) {
+                continue;
+            }
+            let duplicates_group = DuplicatesGroup {
+                group: subgroup
+                    .iter()
+                    .map(|x| DuplicatesGroupItem {
+                        doc_id: x.doc_id(),
+                        content_hash: x.content_hash(),
+                        rank: x.rank(),
+                    })
+                    .collect(),
+            };
+            duplicates_group.write_to_stream(&mut output_writer)?;
+        }
         group.clear();
 
         Ok(())
@@ -249,100 +516,724 @@ pub fn find_duplicates_in_lsh_buckets_files(
     Ok(())
 }
 
+// Connected component of duplicates groups, all items except survivor are removed
+#[derive(Readable, Writable, Debug)]
+struct DuplicatesCluster {
+    survivor: DuplicatesGroupItem,
+    duplicates: Vec<DuplicatesGroupItem>,
+}
+
+fn read_duplicates_groups_keys(input_file: &str) -> Result<HashSet<DocId>> {
+    let mut keys = HashSet::new();
+    let mut reader = stream::Decoder::new(File::open(input_file)?)?;
+    loop {
+        let group = DuplicatesGroup::read_from_stream_unbuffered(&mut reader);
+        if group.as_ref().is_err_and(|e| e.is_eof()) {
+            break;
+        }
+        keys.extend(group?.group.iter().map(|x| x.doc_id));
+    }
+    Ok(keys)
+}
+
+fn load_signatures_from_files(
+    context: &Context,
+    file_indices: &[u32],
+    params: &LshBucketsParams,
+    verification: &Verification,
+    keys: &HashSet<DocId>,
+    progress_bar: &ProgressBar,
+) -> Result<HashMap<DocId, Signature>> {
+    let mut signatures = HashMap::new();
+    for file_index in file_indices {
+        let path = context.input_file(*file_index)?;
+        let mut reader = BatchReader::try_new(path, params.text_columns())?;
+        let mut row_index = 0u32;
+        while let Some((record_batch, texts)) = reader.next()? {
+            let parts = read_weighted_parts(&record_batch, path, params)?;
+            for (i, text) in texts.iter().enumerate() {
+                let key = DocId {
+                    file_index: *file_index,
+                    row_index,
+                };
+                row_index += 1;
+                let text = match text {
+                    Some(text) if keys.contains(&key) => text,
+                    _ => continue,
+                };
+                let signature = match verification {
+                    Verification::Exact { .. } => Signature::Shingles(text_shingles(
+                        &params.normalization().apply(text),
+                        params.minhash_params(),
+                    )),
+                    _ => Signature::MinHash(
+                        document_minhash(text, parts.as_ref().and_then(|x| x[i].as_ref()), params)
+                            .ok_or(anyhow!(
+                                "Cannot compute signature for {}:{}",
+                                path,
+                                key.row_index
+                            ))?,
+                    ),
+                };
+                signatures.insert(key, signature);
+            }
+        }
+        progress_bar.inc(1);
+    }
+    Ok(signatures)
+}
+
+// Computes signatures of all documents from duplicates groups, only files
+// which contain such documents are read, every worker sends signatures of its files
+// or its error through the channel
+pub fn load_signatures(
+    context: &Context,
+    params: &LshBucketsParams,
+    verification: &Verification,
+    keys: HashSet<DocId>,
+    n_workers: usize,
+) -> Result<HashMap<DocId, Signature>> {
+    let mut files: Vec<u32> = keys
+        .iter()
+        .map(|x| x.file_index)
+        .collect::<HashSet<u32>>()
+        .into_iter()
+        .collect();
+    files.sort();
+
+    info!(
+        "Started loading signatures of {} documents from {} files",
+        keys.len(),
+        files.len()
+    );
+
+    let keys = Arc::new(keys);
+    let progress_bar = Arc::new(ProgressBar::new(files.len() as u64));
+    let pool = ThreadPool::new(n_workers);
+    let (sender, receiver) = mpsc::channel();
+
+    let num_files_per_worker = cmp::max(files.len() / n_workers, 1);
+    for start in (0..files.len()).step_by(num_files_per_worker) {
+        let worker_files =
+            Vec::from(&files[start..cmp::min(files.len(), start + num_files_per_worker)]);
+        let context = context.clone();
+        let params = params.clone();
+        let verification = verification.clone();
+        let keys = keys.clone();
+        let progress_bar = progress_bar.clone();
+        let sender = sender.clone();
+        let worker = move || {
+            let result = load_signatures_from_files(
+                &context,
+                &worker_files,
+                &params,
+                &verification,
+                &keys,
+                &progress_bar,
+            );
+            // receiver is dropped on the first error, later results are not needed
+            let _ = sender.send(result);
+        };
+        pool.execute(worker);
+    }
+    drop(sender);
+
+    let mut signatures = HashMap::with_capacity(keys.len());
+    for result in receiver {
+        signatures.extend(result?);
+    }
+    join_workers(&pool)?;
+
+    progress_bar.finish();
+
+    info!("Stopped loading signatures");
+
+    Ok(signatures)
+}
+
+// Documents of duplicates groups checked by verification, a document is one candidate
+// however many groups it is in, it is rejected if no representative of any group verifies it
+pub struct VerificationStats {
+    pub num_candidates: u64,
+    pub num_rejected: u64,
+}
+
+pub fn find_clusters_in_duplicates_groups(
+    context: &Context,
+    params: &LshBucketsParams,
+    verification: &Verification,
+    n_workers: usize,
+) -> Result<VerificationStats> {
+    let input_file = context.duplicats_groups_path();
+    let output_file = context.duplicates_clusters_path();
+    info!("Starting finding clusters in groups file {}", input_file);
+
+    let signatures = if verification.is_enabled() {
+        let keys = read_duplicates_groups_keys(&input_file)?;
+        load_signatures(context, params, verification, keys, n_workers)?
+    } else {
+        HashMap::new()
+    };
+
+    // items are identified by document id, content hash and rank are stored aside
+    let mut union_find = UnionFind::default();
+    let mut content_hashes = HashMap::new();
+    let mut ranks = HashMap::new();
+    let mut num_groups = 0u64;
+    let mut candidates = HashSet::new();
+    let mut verified = HashSet::new();
+
+    let mut reader = stream::Decoder::new(File::open(&input_file)?)?;
+    loop {
+        let group = DuplicatesGroup::read_from_stream_unbuffered(&mut reader);
+        if group.as_ref().is_err_and(|e| e.is_eof()) {
+            break;
+        }
+
+        let items = &group?.group;
+        for item in items {
+            ranks.insert(item.doc_id, item.rank);
+            content_hashes.insert(item.doc_id, item.content_hash);
+        }
+        if !verification.is_enabled() {
+            for item in items.iter().skip(1) {
+                union_find.union(&items[0].doc_id, &item.doc_id);
+            }
+        } else {
+            // every item is joined to the first verified representative of the group,
+            // items without verified representative start new representatives
+            let mut representatives: Vec<DocId> = Vec::new();
+            for key in items.iter().map(|x| x.doc_id) {
+                let signature = signatures
+                    .get(&key)
+                    .ok_or(anyhow!("Cannot find signature for {:?}", key))?;
+                if !representatives.is_empty() {
+                    candidates.insert(key);
+                }
+                let representative = representatives
+                    .iter()
+                    .find(|x| verification.verify(&signatures[*x], signature));
+                match representative {
+                    Some(representative) => {
+                        union_find.union(representative, &key);
+                        verified.insert(key);
+                    }
+                    None => representatives.push(key),
+                }
+            }
+        }
+        num_groups += 1;
+    }
+
+    let num_candidates = candidates.len() as u64;
+    let num_rejected = candidates.difference(&verified).count() as u64;
+    if verification.is_enabled() {
+        info!(
+            "Verified candidate documents: {}, rejected: {}",
+            num_candidates, num_rejected
+        );
+    }
+
+    let mut output_writer = stream::Encoder::new(BufWriter::new(File::create(output_file)?), 1)?;
+
+    let mut num_clusters = 0u64;
+    let mut num_duplicates = 0u64;
+    for component in union_find.into_components() {
+        if component.len() < 2 {
+            continue;
+        }
+        let mut items: Vec<DuplicatesGroupItem> = component
+            .into_iter()
+            .map(|doc_id| DuplicatesGroupItem {
+                doc_id,
+                content_hash: content_hashes[&doc_id],
+                rank: ranks[&doc_id],
+            })
+            .collect();
+        // reference documents are never removed, so one of them survives if any,
+        // other reference documents are not part of the cluster
+        items.sort_by_key(|x| {
+            (
+                !context.is_reference(&x.doc_id),
+                cmp::Reverse(x.rank),
+                x.content_hash,
+                x.doc_id,
+            )
+        });
+        let survivor = items[0];
+        let duplicates: Vec<DuplicatesGroupItem> = items
+            .into_iter()
+            .skip(1)
+            .filter(|x| !context.is_reference(&x.doc_id))
+            .collect();
+        if duplicates.is_empty() {
+            continue;
+        }
+        let cluster = DuplicatesCluster {
+            survivor,
+            duplicates,
+        };
+        num_clusters += 1;
+        num_duplicates += cluster.duplicates.len() as u64;
+        cluster.write_to_stream(&mut output_writer)?;
+    }
+
+    output_writer.flush()?;
+
+    info!(
+        "Stopped finding clusters, groups: {}, clusters: {}, duplicates: {}",
+        num_groups, num_clusters, num_duplicates
+    );
+
+    Ok(VerificationStats {
+        num_candidates,
+        num_rejected,
+    })
+}
+
+pub fn count_duplicates_groups(context: &Context) -> Result<u64> {
+    let mut reader = stream::Decoder::new(File::open(context.duplicats_groups_path())?)?;
+    let mut result = 0u64;
+    loop {
+        let group = DuplicatesGroup::read_from_stream_unbuffered(&mut reader);
+        if group.as_ref().is_err_and(|e| e.is_eof()) {
+            break;
+        }
+        group?;
+        result += 1;
+    }
+    Ok(result)
+}
+
+pub struct ClustersStats {
+    pub num_clusters: u64,
+    pub num_duplicates: u64,
+    // number of clusters by size, survivor is counted in size
+    pub sizes: BTreeMap<u32, u64>,
+}
+
+pub fn read_clusters_stats(context: &Context) -> Result<ClustersStats> {
+    let mut reader = stream::Decoder::new(File::open(context.duplicates_clusters_path())?)?;
+    let mut result = ClustersStats {
+        num_clusters: 0,
+        num_duplicates: 0,
+        sizes: BTreeMap::new(),
+    };
+    loop {
+        let cluster = DuplicatesCluster::read_from_stream_unbuffered(&mut reader);
+        if cluster.as_ref().is_err_and(|e| e.is_eof()) {
+            break;
+        }
+        let cluster = cluster?;
+        result.num_clusters += 1;
+        result.num_duplicates += cluster.duplicates.len() as u64;
+        *result
+            .sizes
+            .entry(cluster.duplicates.len() as u32 + 1)
+            .or_default() += 1;
+    }
+    Ok(result)
+}
+
+const EXPORT_BATCH_SIZE: usize = 65536;
+
+fn export_clusters_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("cluster_id", DataType::UInt64, false),
+        Field::new("source_path", DataType::Utf8, false),
+        Field::new("row_index", DataType::UInt32, false),
+        Field::new("content_hash", DataType::UInt64, false),
+        Field::new("is_survivor", DataType::Boolean, false),
+        Field::new("similarity", DataType::Float64, false),
+    ]))
+}
+
+// Rows of cluster members as (cluster id, member, is survivor, similarity to survivor)
+fn export_clusters_batch(
+    context: &Context,
+    rows: &[(u64, DuplicatesGroupItem, bool, f64)],
+    schema: SchemaRef,
+) -> Result<RecordBatch> {
+    let cluster_id: UInt64Array = rows.iter().map(|x| Some(x.0)).collect();
+    let source_path: StringArray = rows
+        .iter()
+        .map(|x| context.input_file(x.1.doc_id.file_index).map(Some))
+        .collect::<Result<_>>()?;
+    let row_index: UInt32Array = rows.iter().map(|x| Some(x.1.doc_id.row_index)).collect();
+    let content_hash: UInt64Array = rows.iter().map(|x| Some(x.1.content_hash)).collect();
+    let is_survivor: BooleanArray = rows.iter().map(|x| Some(x.2)).collect();
+    let similarity: Float64Array = rows.iter().map(|x| Some(x.3)).collect();
+    Ok(RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(cluster_id),
+            Arc::new(source_path),
+            Arc::new(row_index),
+            Arc::new(content_hash),
+            Arc::new(is_survivor),
+            Arc::new(similarity),
+        ],
+    )?)
+}
+
+// Writes one row per member of every duplicates cluster, cluster ids are the same
+// as in annotations of output files. Similarity to the survivor is estimated by minhash
+// signatures or computed exactly if the run verified candidates exactly,
+// members of exact mode clusters have the same content
+pub fn export_clusters(
+    context: &Context,
+    run_meta: &RunMeta,
+    path: &str,
+    n_workers: usize,
+) -> Result<()> {
+    let params = run_meta.lsh_buckets_params();
+    let clusters_path = context.duplicates_clusters_path();
+    if !Path::new(&clusters_path).exists() {
+        return Err(anyhow!(
+            "Cannot find {}, deduplication must be run in near or exact mode first",
+            clusters_path
+        ));
+    }
+    info!("Started exporting clusters to {}", path);
+
+    let signatures = if params.mode().is_exact() {
+        HashMap::new()
+    } else {
+        let verification = match run_meta.verification() {
+            Verification::Exact { .. } => Verification::Exact { threshold: 0.0 },
+            _ => Verification::MinHash { threshold: 0.0 },
+        };
+        let mut keys = HashSet::new();
+        let mut reader = stream::Decoder::new(File::open(&clusters_path)?)?;
+        loop {
+            let cluster = DuplicatesCluster::read_from_stream_unbuffered(&mut reader);
+            if cluster.as_ref().is_err_and(|e| e.is_eof()) {
+                break;
+            }
+            let cluster = cluster?;
+            keys.insert(cluster.survivor.doc_id);
+            keys.extend(cluster.duplicates.iter().map(|x| x.doc_id));
+        }
+        load_signatures(context, params, &verification, keys, n_workers)?
+    };
+
+    let schema = export_clusters_schema();
+    let mut writer = BatchWriter::new(path, schema.clone())?;
+    let mut rows = Vec::new();
+    let mut reader = stream::Decoder::new(File::open(&clusters_path)?)?;
+    for cluster_id in 0u64.. {
+        let cluster = DuplicatesCluster::read_from_stream_unbuffered(&mut reader);
+        if cluster.as_ref().is_err_and(|e| e.is_eof()) {
+            break;
+        }
+
+        let cluster = cluster?;
+        rows.push((cluster_id, cluster.survivor, true, 1.0));
+        for item in &cluster.duplicates {
+            let similarity = match (
+                signatures.get(&cluster.survivor.doc_id),
+                signatures.get(&item.doc_id),
+            ) {
+                (Some(survivor), Some(duplicate)) => verification::similarity(survivor, duplicate),
+                _ => 1.0,
+            };
+            rows.push((cluster_id, *item, false, similarity));
+        }
+        if rows.len() >= EXPORT_BATCH_SIZE {
+            writer.write(&export_clusters_batch(context, &rows, schema.clone())?)?;
+            rows.clear();
+        }
+    }
+    writer.write(&export_clusters_batch(context, &rows, schema)?)?;
+    let num_rows = writer.num_rows();
+    writer.close()?;
+
+    info!("Stopped exporting clusters, members: {}", num_rows);
+
+    Ok(())
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum FilterMode {
+    // duplicates are dropped from output files
+    Remove,
+    // all rows are kept, duplicates are tagged in additional columns
+    Annotate,
+}
+
+const ANNOTATION_COLUMNS: [&str; 4] = ["is_duplicate", "cluster_id", "cluster_size", "survivor_id"];
+
+// Row of a duplicates cluster, survivors are stored too for annotations
 #[derive(Readable, Writable)]
 struct Filter {
-    content_hash: u64,
+    row_index: u32,
+    cluster_id: u64,
+    cluster_size: u32,
+    survivor: DocId,
+}
+
+impl Filter {
+    fn is_duplicate(&self, file_index: u32) -> bool {
+        self.survivor
+            != DocId {
+                file_index,
+                row_index: self.row_index,
+            }
+    }
+}
+
+// Documents of clusters marked as false positives in diff browser, they are never removed,
+// documents are stored by their names, so marks survive recomputing of clusters
+pub fn read_allowlist(context: &Context) -> Result<HashSet<String>> {
+    let path = context.allowlist_path();
+    if !Path::new(&path).exists() {
+        return Ok(HashSet::new());
+    }
+    Ok(read_to_string(path)?
+        .lines()
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect())
+}
+
+pub fn write_allowlist(context: &Context, allowlist: &HashSet<String>) -> Result<()> {
+    let mut names: Vec<&String> = allowlist.iter().collect();
+    names.sort();
+    let mut writer = BufWriter::new(File::create(context.allowlist_path())?);
+    for name in names {
+        writeln!(writer, "{}", name)?;
+    }
+    writer.flush()?;
+    Ok(())
 }
 
 pub fn build_filters(context: &Context) -> Result<()> {
     info!("Started building filters");
 
+    // filters are rebuilt for every file, so files without clusters left,
+    // e.g. after clusters are added to the allowlist, do not keep stale filters
+    remove_dir_all(context.filters_folder_path())?;
+    create_dir_all(context.filters_folder_path())?;
+
+    let allowlist = read_allowlist(context)?;
+    let mut num_allowed = 0u64;
     let mut writers = HashMap::new();
 
-    let mut reader = stream::Decoder::new(File::open(context.duplicats_groups_path())?)?;
-    loop {
-        let group = DuplicatesGroup::read_from_stream_unbuffered(&mut reader);
-        if group.as_ref().is_err_and(|e| e.is_eof()) {
+    let mut reader = stream::Decoder::new(File::open(context.duplicates_clusters_path())?)?;
+    for cluster_id in 0u64.. {
+        let cluster = DuplicatesCluster::read_from_stream_unbuffered(&mut reader);
+        if cluster.as_ref().is_err_and(|e| e.is_eof()) {
             break;
         }
 
-        let rows = &group?.group;
-        for i in 1..rows.len() {
-            let row = &rows[i];
-            if !writers.contains_key(&row.path_hash) {
-                writers.insert(
-                    row.path_hash,
-                    stream::Encoder::new(
-                        BufWriter::new(File::create(context.filter_file_path(row.path_hash))?),
-                        1,
-                    )?,
-                );
+        let mut cluster = cluster?;
+        if !allowlist.is_empty() {
+            let num_duplicates = cluster.duplicates.len();
+            let mut duplicates = Vec::with_capacity(num_duplicates);
+            for duplicate in cluster.duplicates {
+                if !allowlist.contains(&context.document_name(&duplicate.doc_id)?) {
+                    duplicates.push(duplicate);
+                }
+            }
+            cluster.duplicates = duplicates;
+            num_allowed += (num_duplicates - cluster.duplicates.len()) as u64;
+            if cluster.duplicates.is_empty() {
+                continue;
+            }
+        }
+        let cluster_size = cluster.duplicates.len() as u32 + 1;
+        for row in iter::once(&cluster.survivor).chain(&cluster.duplicates) {
+            if context.is_reference(&row.doc_id) {
+                continue;
+            }
+            let file_index = row.doc_id.file_index;
+            if let Entry::Vacant(entry) = writers.entry(file_index) {
+                entry.insert(stream::Encoder::new(
+                    BufWriter::new(File::create(context.filter_file_path(file_index))?),
+                    1,
+                )?);
             }
 
-            let stream = writers.get_mut(&row.path_hash).unwrap();
+            let stream = writers.get_mut(&file_index).unwrap();
             let filter = Filter {
-                content_hash: row.content_hash,
+                row_index: row.doc_id.row_index,
+                cluster_id,
+                cluster_size,
+                survivor: cluster.survivor.doc_id,
             };
             filter.write_to_stream(stream)?;
         }
     }
 
-    for (_, val) in &mut writers {
+    for val in writers.values_mut() {
         val.flush()?;
     }
 
+    if num_allowed > 0 {
+        info!(
+            "Documents kept by allowlist {}: {}",
+            context.allowlist_path(),
+            num_allowed
+        );
+    }
     info!("Stopped building filters");
 
     Ok(())
 }
 
+fn annotated_schema(schema: &SchemaRef, path: &str) -> Result<SchemaRef> {
+    for name in ANNOTATION_COLUMNS {
+        if schema.field_with_name(name).is_ok() {
+            return Err(anyhow!(
+                "Column {} of file {} conflicts with annotation columns",
+                name,
+                path
+            ));
+        }
+    }
+
+    let mut fields: Vec<FieldRef> = schema.fields().iter().cloned().collect();
+    fields.push(Arc::new(Field::new(
+        ANNOTATION_COLUMNS[0],
+        DataType::Boolean,
+        false,
+    )));
+    fields.push(Arc::new(Field::new(
+        ANNOTATION_COLUMNS[1],
+        DataType::UInt64,
+        true,
+    )));
+    fields.push(Arc::new(Field::new(
+        ANNOTATION_COLUMNS[2],
+        DataType::UInt32,
+        true,
+    )));
+    fields.push(Arc::new(Field::new(
+        ANNOTATION_COLUMNS[3],
+        DataType::Utf8,
+        true,
+    )));
+    Ok(Arc::new(Schema::new_with_metadata(
+        fields,
+        schema.metadata().clone(),
+    )))
+}
+
+// Annotation columns are null for rows which are not in any cluster,
+// survivor id is the survivor's input path and row index
+fn annotate_record_batch(
+    context: &Context,
+    record_batch: &RecordBatch,
+    rows: &[Option<&Filter>],
+    file_index: u32,
+    schema: SchemaRef,
+) -> Result<RecordBatch> {
+    let is_duplicate: BooleanArray = rows
+        .iter()
+        .map(|x| Some(x.is_some_and(|x| x.is_duplicate(file_index))))
+        .collect();
+    let cluster_id: UInt64Array = rows.iter().map(|x| x.map(|x| x.cluster_id)).collect();
+    let cluster_size: UInt32Array = rows.iter().map(|x| x.map(|x| x.cluster_size)).collect();
+    let survivor_id: StringArray = rows
+        .iter()
+        .map(|x| x.map(|x| context.document_name(&x.survivor)).transpose())
+        .collect::<Result<_>>()?;
+
+    let mut columns = record_batch.columns().to_vec();
+    columns.push(Arc::new(is_duplicate));
+    columns.push(Arc::new(cluster_id));
+    columns.push(Arc::new(cluster_size));
+    columns.push(Arc::new(survivor_id));
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn apply_filter_to_files(
     context: &Context,
-    files: &[String],
-    column: &String,
-    output_folder: &String,
+    file_indices: &[u32],
+    params: &LshBucketsParams,
+    filter_mode: FilterMode,
+    output: &Output,
     progress_bar: &ProgressBar,
-    total_rows: &mut Arc<AtomicU64>,
-    filtered_rows: &mut Arc<AtomicU64>,
+    total_rows: &AtomicU64,
+    filtered_rows: &AtomicU64,
 ) -> Result<()> {
-    for file in files {
-        let filter_file = context.filter_file_path(Context::hash_path(&file));
-        if !Path::new(&filter_file).exists() {
-            debug!("There is no filter file for {}, nothing to filter", file);
-            progress_bar.inc(1);
-            continue;
-        }
-        debug!("Starting filter {} with filter file {}", file, filter_file);
-
-        let mut filters_set = HashSet::new();
-        let mut filter_reader = stream::Decoder::new(File::open(filter_file)?)?;
-        loop {
-            let filter = Filter::read_from_stream_unbuffered(&mut filter_reader);
-            if filter.as_ref().is_err_and(|e| e.is_eof()) {
-                break;
+    for file_index in file_indices {
+        let file = context.input_file(*file_index)?;
+        let mut filters = HashMap::new();
+        let filter_file = context.filter_file_path(*file_index);
+        if Path::new(&filter_file).exists() {
+            debug!("Starting filter {} with filter file {}", file, filter_file);
+
+            let mut filter_reader = stream::Decoder::new(File::open(filter_file)?)?;
+            loop {
+                let filter = Filter::read_from_stream_unbuffered(&mut filter_reader);
+                if filter.as_ref().is_err_and(|e| e.is_eof()) {
+                    break;
+                }
+                let filter = filter?;
+                filters.insert(filter.row_index, filter);
             }
-            filters_set.insert(filter?.content_hash);
+        } else {
+            debug!("There is no filter file for {}, nothing to filter", file);
         }
 
-        let output_file_path = format!("{}/{:x}.parquet.zst", output_folder, md5::compute(&file));
+        let output_file_path = output.file_path(file)?;
         debug!("Writing {}", output_file_path);
-        let mut writer = ParquetWriter::new(&output_file_path, column)?;
 
-        let mut reader = ParquetReader::try_new(&file, &column)?;
+        let mut reader = BatchReader::try_new(file, params.text_columns())?;
+        let schema = match filter_mode {
+            FilterMode::Remove => reader.schema(),
+            FilterMode::Annotate => annotated_schema(&reader.schema(), file)?,
+        };
+        let mut writer = BatchWriter::new(&output_file_path, schema.clone())?;
 
         let mut num_total = 0u64;
         let mut num_filtered = 0u64;
 
-        while reader.has_data_left()? {
-            let text = reader.next()?;
-            num_total += 1;
-            if filters_set.contains(&hash::<u64>(&text)) {
-                num_filtered += 1;
-                continue;
-            }
-            writer.write(text)?;
+        while let Some((record_batch, texts)) = reader.next()? {
+            let rows: Vec<Option<&Filter>> = (0..record_batch.num_rows())
+                .map(|i| filters.get(&(num_total as u32 + i as u32)))
+                .collect();
+            let mask: BooleanArray = rows
+                .iter()
+                .zip(&texts)
+                .map(|(x, text)| {
+                    Some(
+                        !x.is_some_and(|x| x.is_duplicate(*file_index))
+                            && !params.text_columns().is_skipped(text),
+                    )
+                })
+                .collect();
+            let to_write = match filter_mode {
+                FilterMode::Remove => filter_record_batch(&record_batch, &mask)?,
+                FilterMode::Annotate => {
+                    let record_batch = annotate_record_batch(
+                        context,
+                        &record_batch,
+                        &rows,
+                        *file_index,
+                        schema.clone(),
+                    )?;
+                    let kept: BooleanArray = texts
+                        .iter()
+                        .map(|x| Some(!params.text_columns().is_skipped(x)))
+                        .collect();
+                    filter_record_batch(&record_batch, &kept)?
+                }
+            };
+
+            num_total += record_batch.num_rows() as u64;
+            num_filtered += mask.false_count() as u64;
+            writer.write(&to_write)?;
         }
 
+        output.add_manifest_entry(
+            file,
+            &output_file_path,
+            reader.num_rows(),
+            writer.num_rows(),
+        );
         writer.close()?;
         debug!(
             "Stopped filter {}, num_total: {}, num_filtered: {}",
@@ -360,13 +1251,14 @@ fn apply_filter_to_files(
 
 pub fn apply_filters(
     context: &Context,
-    column_name: &String,
-    output_folder: &String,
+    params: &LshBucketsParams,
+    filter_mode: FilterMode,
+    output: &Output,
     n_workers: usize,
 ) -> Result<()> {
     info!("Started applying filters");
 
-    let mut input_files = context.input_files().clone();
+    let mut input_files: Vec<u32> = (0..context.input_files().len() as u32).collect();
     input_files.shuffle(&mut thread_rng());
 
     let total_rows = Arc::new(AtomicU64::new(0));
@@ -381,20 +1273,21 @@ pub fn apply_filters(
             &input_files[start..cmp::min(input_files.len(), start + num_files_per_worker)],
         );
         let context = context.clone();
-        let column_name = column_name.clone();
-        let output_folder = output_folder.clone();
+        let params = params.clone();
+        let output = output.clone();
         let progress_bar = progress_bar.clone();
-        let mut total_rows = total_rows.clone();
-        let mut filtered_rows = filtered_rows.clone();
+        let total_rows = total_rows.clone();
+        let filtered_rows = filtered_rows.clone();
         let worker = move || {
             let result = apply_filter_to_files(
                 &context,
                 &worker_files,
-                &column_name,
-                &output_folder,
-                &*progress_bar,
-                &mut total_rows,
-                &mut filtered_rows,
+                &params,
+                filter_mode,
+                &output,
+                &progress_bar,
+                &total_rows,
+                &filtered_rows,
             );
             if result.is_err() {
                 error!("{}", result.err().unwrap());
@@ -403,7 +1296,7 @@ pub fn apply_filters(
         };
         pool.execute(worker);
     }
-    pool.join();
+    join_workers(&pool)?;
 
     info!(
         "Total rows processed: {}, total filtered: {}",
@@ -416,44 +1309,263 @@ pub fn apply_filters(
     Ok(())
 }
 
-pub fn show_diff(context: &Context, column: &String, limit: usize) -> Result<()> {
-    let mut reader = stream::Decoder::new(File::open(context.duplicats_groups_path())?)?;
+pub struct DiffOptions {
+    pub sampling: DiffSampling,
+    pub limit: usize,
+    pub min_similarity: f64,
+    pub max_similarity: f64,
+    pub seed: Option<u64>,
+}
 
-    let mut groups = Vec::new();
-    for _ in 0..limit {
-        let group = DuplicatesGroup::read_from_stream_unbuffered(&mut reader);
-        if group.as_ref().is_err_and(|e| e.is_eof()) {
+impl DiffOptions {
+    fn is_filtered(&self) -> bool {
+        self.min_similarity > 0.0 || self.max_similarity < 1.0
+    }
+}
+
+// Power of two range of cluster size
+fn cluster_size_stratum(cluster: &DuplicatesCluster) -> u32 {
+    usize::BITS - cluster.duplicates.len().leading_zeros()
+}
+
+// Order in which clusters are shown, stratified order takes clusters
+// from every stratum in turn, ties of largest clusters keep order of the clusters file
+fn sample_clusters(
+    clusters: &[DuplicatesCluster],
+    sampling: DiffSampling,
+    rng: &mut ChaCha8Rng,
+) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..clusters.len()).collect();
+    match sampling {
+        DiffSampling::First => order,
+        DiffSampling::Random => {
+            order.shuffle(rng);
+            order
+        }
+        DiffSampling::Stratified => {
+            order.shuffle(rng);
+            let mut strata: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+            for i in order {
+                strata
+                    .entry(cluster_size_stratum(&clusters[i]))
+                    .or_default()
+                    .push(i);
+            }
+            let mut strata: Vec<_> = strata.into_values().map(|x| x.into_iter()).collect();
+            let mut order = Vec::with_capacity(clusters.len());
+            while order.len() < clusters.len() {
+                order.extend(strata.iter_mut().filter_map(|x| x.next()));
+            }
+            order
+        }
+        DiffSampling::Largest => {
+            order.sort_by_key(|&i| cmp::Reverse(clusters[i].duplicates.len()));
+            order
+        }
+    }
+}
+
+// Reads sampled clusters with texts, duplicates are compared with survivors by minhash
+// signatures, clusters without duplicates in the similarity range are skipped
+pub fn read_diff_clusters(
+    context: &Context,
+    run_meta: &RunMeta,
+    column: &TextColumns,
+    options: &DiffOptions,
+    n_workers: usize,
+) -> Result<Vec<DiffCluster>> {
+    let params = run_meta.lsh_buckets_params();
+    let mut clusters = Vec::new();
+    let mut reader = stream::Decoder::new(File::open(context.duplicates_clusters_path())?)?;
+    loop {
+        let cluster = DuplicatesCluster::read_from_stream_unbuffered(&mut reader);
+        if cluster.as_ref().is_err_and(|e| e.is_eof()) {
             break;
         }
-        groups.push(group?);
+        clusters.push(cluster?);
     }
 
-    let mut content_hashes = HashSet::new();
-    let mut files = HashSet::new();
+    let mut rng = match options.seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    let mut order = sample_clusters(&clusters, options.sampling, &mut rng);
+    // all clusters are compared if they are filtered, otherwise only sampled ones
+    if !options.is_filtered() {
+        order.truncate(options.limit);
+    }
 
-    for group in &groups {
-        for item in &group.group {
-            content_hashes.insert(item.content_hash);
-            files.extend(context.hash_to_input_files(item.path_hash));
+    let signatures = if params.mode().is_exact() {
+        HashMap::new()
+    } else {
+        let keys: HashSet<DocId> = order
+            .iter()
+            .flat_map(|&i| iter::once(&clusters[i].survivor).chain(&clusters[i].duplicates))
+            .map(|x| x.doc_id)
+            .collect();
+        let verification = Verification::MinHash { threshold: 0.0 };
+        load_signatures(context, params, &verification, keys, n_workers)?
+    };
+    let similarity = |survivor: &DocId, duplicate: &DocId| match (
+        signatures.get(survivor),
+        signatures.get(duplicate),
+    ) {
+        (Some(survivor), Some(duplicate)) => verification::similarity(survivor, duplicate),
+        _ => 1.0,
+    };
+
+    let mut sampled = Vec::new();
+    for i in order {
+        let cluster = &clusters[i];
+        let duplicates: Vec<(DocId, f64)> = cluster
+            .duplicates
+            .iter()
+            .map(|x| (x.doc_id, similarity(&cluster.survivor.doc_id, &x.doc_id)))
+            .filter(|x| x.1 >= options.min_similarity && x.1 <= options.max_similarity)
+            .collect();
+        if duplicates.is_empty() {
+            continue;
+        }
+        sampled.push((i as u64, cluster, duplicates));
+        if sampled.len() == options.limit {
+            break;
+        }
+    }
+
+    let mut doc_ids = HashSet::new();
+    let mut files = HashSet::new();
+    for (_, cluster, duplicates) in &sampled {
+        for doc_id in iter::once(cluster.survivor.doc_id).chain(duplicates.iter().map(|x| x.0)) {
+            doc_ids.insert(doc_id);
+            files.insert(doc_id.file_index);
         }
     }
 
     let mut content = HashMap::new();
-    for file in files {
-        let mut reader = ParquetReader::try_new(&file, &column)?;
+    for file_index in files {
+        let mut reader = TextReader::try_new(context.input_file(file_index)?, column)?;
+        let mut row_index = 0u32;
         while reader.has_data_left()? {
             let text = reader.next()?;
-            content.insert(hash::<u64>(&text), text);
+            let doc_id = DocId {
+                file_index,
+                row_index,
+            };
+            row_index += 1;
+            if doc_ids.contains(&doc_id) {
+                content.insert(doc_id, text.unwrap_or_default());
+            }
         }
     }
 
-    for group in &groups {
-        let (item0, item1) = (&group.group[0], &group.group[1]);
-        diff::print_diff(
-            content.get(&item0.content_hash).unwrap(),
-            content.get(&item1.content_hash).unwrap(),
-        );
-    }
+    let member = |doc_id: DocId, similarity: f64| -> Result<DiffMember> {
+        Ok(DiffMember {
+            doc_id,
+            path: context.input_file(doc_id.file_index)?.clone(),
+            similarity,
+            text: content.get(&doc_id).cloned().unwrap_or_default(),
+        })
+    };
+    sampled
+        .into_iter()
+        .map(|(cluster_id, cluster, duplicates)| {
+            Ok(DiffCluster {
+                cluster_id,
+                members: iter::once(&cluster.survivor)
+                    .chain(&cluster.duplicates)
+                    .map(|x| x.doc_id)
+                    .collect(),
+                survivor: member(cluster.survivor.doc_id, 1.0)?,
+                duplicates: duplicates
+                    .into_iter()
+                    .map(|(doc_id, similarity)| member(doc_id, similarity))
+                    .collect::<Result<_>>()?,
+            })
+        })
+        .collect()
+}
 
+// Prints diffs or writes them as html report
+pub fn show_diff(
+    context: &Context,
+    run_meta: &RunMeta,
+    column: &TextColumns,
+    options: &DiffOptions,
+    html: Option<&str>,
+    n_workers: usize,
+) -> Result<()> {
+    let clusters = read_diff_clusters(context, run_meta, column, options, n_workers)?;
+    match html {
+        Some(path) => diff::write_html_report(path, &clusters)?,
+        None => {
+            for cluster in &clusters {
+                diff::print_cluster(cluster);
+            }
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn item(file_index: u32, row_index: u32) -> DuplicatesGroupItem {
+        DuplicatesGroupItem {
+            doc_id: DocId {
+                file_index,
+                row_index,
+            },
+            content_hash: 0,
+            rank: 0,
+        }
+    }
+
+    #[test]
+    fn allowlisted_clusters_remove_stale_filters() {
+        let folder = env::temp_dir().join(format!("operations_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(folder.join("input")).unwrap();
+        for name in ["a.parquet", "b.parquet"] {
+            fs::write(folder.join("input").join(name), "").unwrap();
+        }
+        let context = Context::new(
+            folder.join("input").display().to_string(),
+            "*.parquet".to_string(),
+            folder.join("tmp").display().to_string(),
+        )
+        .unwrap();
+        let clusters = [
+            DuplicatesCluster {
+                survivor: item(0, 0),
+                duplicates: vec![item(0, 1)],
+            },
+            DuplicatesCluster {
+                survivor: item(1, 0),
+                duplicates: vec![item(1, 1), item(1, 2)],
+            },
+        ];
+        let mut writer =
+            stream::Encoder::new(File::create(context.duplicates_clusters_path()).unwrap(), 1)
+                .unwrap()
+                .auto_finish();
+        for cluster in &clusters {
+            cluster.write_to_stream(&mut writer).unwrap();
+        }
+        drop(writer);
+
+        build_filters(&context).unwrap();
+        assert!(Path::new(&context.filter_file_path(0)).exists());
+        assert!(Path::new(&context.filter_file_path(1)).exists());
+
+        let allowlist = [item(1, 1), item(1, 2)]
+            .iter()
+            .map(|x| context.document_name(&x.doc_id).unwrap())
+            .collect();
+        write_allowlist(&context, &allowlist).unwrap();
+        build_filters(&context).unwrap();
+        assert!(Path::new(&context.filter_file_path(0)).exists());
+        assert!(!Path::new(&context.filter_file_path(1)).exists());
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
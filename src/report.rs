@@ -0,0 +1,125 @@
+use crate::{
+    batch_io::Output,
+    context::Context,
+    operations::{ClustersStats, VerificationStats},
+    run_meta::RunMeta,
+};
+use anyhow::Result;
+use log::info;
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::BufWriter,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+// Statistics of a deduplication run, stages which are skipped on resume have no wall time
+// and no statistics
+pub struct RunReport {
+    started_at: u64,
+    started: Instant,
+    stages: Vec<(String, f64)>,
+    num_groups: Option<u64>,
+    clusters: Option<ClustersStats>,
+    verification: Option<VerificationStats>,
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        Self {
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+            started: Instant::now(),
+            stages: Vec::new(),
+            num_groups: None,
+            clusters: None,
+            verification: None,
+        }
+    }
+
+    // Runs the stage and records its wall time
+    pub fn stage<T>(&mut self, name: &str, stage: impl FnOnce() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let result = stage()?;
+        self.stages
+            .push((name.to_string(), started.elapsed().as_secs_f64()));
+        Ok(result)
+    }
+
+    pub fn set_num_groups(&mut self, num_groups: u64) {
+        self.num_groups = Some(num_groups);
+    }
+
+    pub fn set_clusters(&mut self, clusters: ClustersStats) {
+        self.clusters = Some(clusters);
+    }
+
+    pub fn set_verification(&mut self, verification: VerificationStats) {
+        self.verification = Some(verification);
+    }
+
+    fn to_json(&self, run_meta: &RunMeta, output: &Output) -> Result<Value> {
+        let files = output.manifest()?;
+        let sum = |key: &str| files.iter().map(|x| x[key].as_u64().unwrap()).sum::<u64>();
+        let stages: Vec<Value> = self
+            .stages
+            .iter()
+            .map(|(name, seconds)| json!({ "name": name, "seconds": seconds }))
+            .collect();
+        let clusters = self.clusters.as_ref().map(|x| {
+            let histogram: Vec<Value> = x
+                .sizes
+                .iter()
+                .map(|(size, count)| json!({ "size": size, "clusters": count }))
+                .collect();
+            json!({
+                "clusters": x.num_clusters,
+                "duplicates": x.num_duplicates,
+                "cluster_sizes": histogram,
+            })
+        });
+        let verification = self.verification.as_ref().map(|x| {
+            json!({
+                "candidate_documents": x.num_candidates,
+                "rejected_documents": x.num_rejected,
+                "rejection_rate": if x.num_candidates == 0 {
+                    0.0
+                } else {
+                    x.num_rejected as f64 / x.num_candidates as f64
+                },
+            })
+        });
+
+        Ok(json!({
+            "started_at": self.started_at,
+            "seconds": self.started.elapsed().as_secs_f64(),
+            "command_line": std::env::args().collect::<Vec<String>>(),
+            "parameters": run_meta.lsh_buckets_params(),
+            "verification": run_meta.verification(),
+            "input_rows": sum("input_rows"),
+            "output_rows": sum("output_rows"),
+            "input_bytes": sum("input_bytes"),
+            "output_bytes": sum("output_bytes"),
+            "groups": self.num_groups,
+            "clusters": clusters,
+            "candidates": verification,
+            "stages": stages,
+            "files": files,
+        }))
+    }
+
+    // Writes report.json to tmp and output folders
+    pub fn write(&self, context: &Context, run_meta: &RunMeta, output: &Output) -> Result<()> {
+        let report = self.to_json(run_meta, output)?;
+        for path in [
+            context.run_report_path(),
+            format!("{}/report.json", output.folder()),
+        ] {
+            let writer = BufWriter::new(File::create(&path)?);
+            serde_json::to_writer_pretty(writer, &report)?;
+            info!("Run report is written to {}", path);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{lsh::LshBucketsParams, verification::Verification};
+use anyhow::Result;
+use log::warn;
+use speedy::{Readable, Writable};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use zstd::stream;
+
+// Parameters of the whole deduplication run, they are stored in tmp folder,
+// so intermediate results are reused only by runs with the same parameters
+#[derive(Readable, Writable, PartialEq, Clone, Debug)]
+pub struct RunMeta {
+    // documents ids depend on the list of input files
+    input_files_hash: u64,
+    lsh_buckets_params: LshBucketsParams,
+    verification: Verification,
+}
+
+impl RunMeta {
+    pub fn new(
+        input_files_hash: u64,
+        lsh_buckets_params: LshBucketsParams,
+        verification: Verification,
+    ) -> Self {
+        Self {
+            input_files_hash,
+            lsh_buckets_params,
+            verification,
+        }
+    }
+
+    pub fn input_files_hash(&self) -> u64 {
+        self.input_files_hash
+    }
+
+    pub fn lsh_buckets_params(&self) -> &LshBucketsParams {
+        &self.lsh_buckets_params
+    }
+
+    pub fn verification(&self) -> &Verification {
+        &self.verification
+    }
+
+    pub fn read(path: &str) -> Result<Option<Self>> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        let decoder = stream::Decoder::new(File::open(path)?)?;
+        let meta = Self::read_from_stream_unbuffered(decoder);
+        if let Err(e) = &meta {
+            warn!("Cannot read run meta {}: {}", path, e);
+            return Ok(None);
+        }
+        Ok(Some(meta?))
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut encoder = stream::Encoder::new(BufWriter::new(File::create(path)?), 1)?;
+        self.write_to_stream(&mut encoder)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{
+    batch_io::{BatchReader, BatchWriter, Output, TextColumns},
+    context::Context,
+    lsh::{LshBucketRowsFilesMerger, LshBucketsParams},
+    normalization::Normalization,
+    operations,
+};
+use anyhow::{anyhow, Result};
+use arrow::compute::filter_record_batch;
+use arrow_array::{BooleanArray, RecordBatch};
+use cityhasher::hash;
+use indicatif::ProgressBar;
+use lazy_static::lazy_static;
+use log::{debug, error, info};
+use regex::Regex;
+use serde::Serialize;
+use speedy::{Readable, Writable};
+use std::{
+    cmp,
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use threadpool::ThreadPool;
+
+lazy_static! {
+    static ref PARAGRAPH_SPLITTER: Regex = Regex::new(r"\n[ \t\r]*\n\s*").unwrap();
+}
+
+// Parts of texts which are counted across the corpus
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Segmentation {
+    Lines,
+    // paragraphs are separated by blank lines
+    Paragraphs,
+}
+
+impl Segmentation {
+    // Segments of the text with separators which precede them, the first one has none
+    fn split_with_separators<'a>(&self, text: &'a str) -> Vec<(&'a str, &'a str)> {
+        match self {
+            Segmentation::Lines => text
+                .split('\n')
+                .enumerate()
+                .map(|(i, x)| (if i == 0 { "" } else { "\n" }, x))
+                .collect(),
+            Segmentation::Paragraphs => {
+                let mut result = Vec::new();
+                let mut separator = "";
+                let mut start = 0;
+                for x in PARAGRAPH_SPLITTER.find_iter(text) {
+                    result.push((separator, &text[start..x.start()]));
+                    separator = x.as_str();
+                    start = x.end();
+                }
+                result.push((separator, &text[start..]));
+                result
+            }
+        }
+    }
+
+    // Segments are normalized one by one, since normalization may remove line breaks,
+    // empty segments have no hash and are never stripped
+    fn segment_hash(&self, segment: &str, normalization: &Normalization) -> Option<u64> {
+        let segment = normalization.apply(segment);
+        let segment = segment.trim();
+        if segment.is_empty() {
+            return None;
+        }
+        Some(hash::<u64>(segment))
+    }
+
+    // Hashes of distinct segments of the text
+    pub fn segment_hashes(&self, text: &str, normalization: &Normalization) -> Vec<u64> {
+        let mut result: Vec<u64> = self
+            .split_with_separators(text)
+            .into_iter()
+            .filter_map(|x| self.segment_hash(x.1, normalization))
+            .collect();
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    // Kept segments are joined by their original preceding separators
+    fn strip(
+        &self,
+        text: &str,
+        normalization: &Normalization,
+        frequent: &HashSet<u64>,
+    ) -> (String, u64) {
+        let mut result = String::with_capacity(text.len());
+        let mut num_stripped = 0u64;
+        let mut is_first = true;
+        for (separator, segment) in self.split_with_separators(text) {
+            if self
+                .segment_hash(segment, normalization)
+                .is_some_and(|x| frequent.contains(&x))
+            {
+                num_stripped += 1;
+                continue;
+            }
+            if !is_first {
+                result.push_str(separator);
+            }
+            result.push_str(segment);
+            is_first = false;
+        }
+        if num_stripped == 0 {
+            return (text.to_string(), 0);
+        }
+        (result, num_stripped)
+    }
+}
+
+// Segments which are found in more documents than max_frequency,
+// rows of a segment are adjacent in merged lsh rows and every document has one row per segment
+pub fn find_frequent_segments(context: &Context, max_frequency: u64) -> Result<HashSet<u64>> {
+    info!("Started finding frequent segments");
+
+    let mut merger = LshBucketRowsFilesMerger::new(&[(context.raw_lsh_buckets_folder_path(), 0)])?;
+    let mut result = HashSet::new();
+    let mut num_segments = 0u64;
+    let mut current = None;
+    let mut count = 0u64;
+    while merger.has_data_left() {
+        let row = merger.next()?;
+        if current != Some(row.bucket_hash()) {
+            current = Some(row.bucket_hash());
+            count = 0;
+            num_segments += 1;
+        }
+        count += 1;
+        if count == max_frequency + 1 {
+            result.insert(row.bucket_hash());
+        }
+    }
+
+    info!(
+        "Stopped finding frequent segments, distinct segments: {}, frequent: {}",
+        num_segments,
+        result.len()
+    );
+
+    Ok(result)
+}
+
+fn strip_segments_in_files(
+    context: &Context,
+    file_indices: &[u32],
+    params: &LshBucketsParams,
+    frequent: &HashSet<u64>,
+    output: &Output,
+    progress_bar: &ProgressBar,
+    stripped_segments: &AtomicU64,
+) -> Result<()> {
+    let segmentation = params.mode().segmentation().unwrap();
+    for file_index in file_indices {
+        let file = context.input_file(*file_index)?;
+        let output_file_path = output.file_path(file)?;
+        debug!("Writing {}", output_file_path);
+
+        let text_columns = params.text_columns();
+        let mut reader = BatchReader::try_new(file, text_columns)?;
+        let schema = reader.schema();
+        let column_index = schema.index_of(&text_columns.names()[0])?;
+        let mut writer = BatchWriter::new(&output_file_path, schema.clone())?;
+
+        while let Some((record_batch, texts)) = reader.next()? {
+            let mask: BooleanArray = texts
+                .iter()
+                .map(|x| Some(!text_columns.is_skipped(x)))
+                .collect();
+            let mut changed = Vec::new();
+            for (i, text) in texts.iter().enumerate() {
+                if let Some(text) = text {
+                    let (text, num_stripped) =
+                        segmentation.strip(text, params.normalization(), frequent);
+                    if num_stripped > 0 {
+                        stripped_segments.fetch_add(num_stripped, Ordering::Relaxed);
+                        changed.push((i, text));
+                    }
+                }
+            }
+            let mut columns = record_batch.columns().to_vec();
+            columns[column_index] = TextColumns::replace_texts(&columns[column_index], &changed)?;
+            let record_batch = RecordBatch::try_new(schema.clone(), columns)?;
+            writer.write(&filter_record_batch(&record_batch, &mask)?)?;
+        }
+        output.add_manifest_entry(
+            file,
+            &output_file_path,
+            reader.num_rows(),
+            writer.num_rows(),
+        );
+        writer.close()?;
+
+        progress_bar.inc(1);
+    }
+
+    Ok(())
+}
+
+// Writes all input files with frequent segments stripped from texts
+pub fn strip_frequent_segments(
+    context: &Context,
+    params: &LshBucketsParams,
+    frequent: HashSet<u64>,
+    output: &Output,
+    n_workers: usize,
+) -> Result<()> {
+    info!("Started stripping frequent segments");
+
+    if params.mode().segmentation().is_none() {
+        return Err(anyhow!(
+            "Segments can be stripped only in lines or paragraphs mode"
+        ));
+    }
+
+    let input_files: Vec<u32> = (0..context.input_files().len() as u32).collect();
+    let frequent = Arc::new(frequent);
+    let stripped_segments = Arc::new(AtomicU64::new(0));
+    let progress_bar = Arc::new(ProgressBar::new(input_files.len() as u64));
+    let pool = ThreadPool::new(n_workers);
+
+    let num_files_per_worker = cmp::max(input_files.len() / n_workers, 1);
+    for start in (0..input_files.len()).step_by(num_files_per_worker) {
+        let worker_files = Vec::from(
+            &input_files[start..cmp::min(input_files.len(), start + num_files_per_worker)],
+        );
+        let context = context.clone();
+        let params = params.clone();
+        let frequent = frequent.clone();
+        let output = output.clone();
+        let progress_bar = progress_bar.clone();
+        let stripped_segments = stripped_segments.clone();
+        let worker = move || {
+            let result = strip_segments_in_files(
+                &context,
+                &worker_files,
+                &params,
+                &frequent,
+                &output,
+                &progress_bar,
+                &stripped_segments,
+            );
+            if result.is_err() {
+                error!("{}", result.err().unwrap());
+                panic!();
+            }
+        };
+        pool.execute(worker);
+    }
+    operations::join_workers(&pool)?;
+
+    info!(
+        "Stopped stripping frequent segments, stripped segments: {}",
+        stripped_segments.load(Ordering::Relaxed)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalization::NormalizationStep;
+
+    fn frequent(segmentation: Segmentation, segments: &[&str]) -> HashSet<u64> {
+        segments
+            .iter()
+            .filter_map(|x| segmentation.segment_hash(x, &Normalization::default()))
+            .collect()
+    }
+
+    #[test]
+    fn paragraphs_are_split_by_blank_lines() {
+        let segments = Segmentation::Paragraphs.split_with_separators("a\nb\n\nc\n \t\n\n d");
+        assert_eq!(
+            segments,
+            vec![("", "a\nb"), ("\n\n", "c"), ("\n \t\n\n ", "d")]
+        );
+        let segments = Segmentation::Lines.split_with_separators("a\n\nb");
+        assert_eq!(segments, vec![("", "a"), ("\n", ""), ("\n", "b")]);
+    }
+
+    #[test]
+    fn segments_are_normalized_one_by_one() {
+        let normalization = Normalization::new(vec![NormalizationStep::Lowercase]);
+        let hashes = Segmentation::Lines.segment_hashes("Foo\n\n  \nfoo \nbar", &normalization);
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(
+            Segmentation::Lines.segment_hashes("  \n\t", &normalization),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn stripping_keeps_original_separators() {
+        let segmentation = Segmentation::Paragraphs;
+        let text = "intro\n\n\nfooter\n \n last\n\nfooter";
+        let frequent = frequent(segmentation, &["footer"]);
+        let (stripped, num_stripped) =
+            segmentation.strip(text, &Normalization::default(), &frequent);
+        assert_eq!(stripped, "intro\n \n last");
+        assert_eq!(num_stripped, 2);
+
+        let (stripped, num_stripped) =
+            segmentation.strip("footer\n\n\nbody", &Normalization::default(), &frequent);
+        assert_eq!(stripped, "body");
+        assert_eq!(num_stripped, 1);
+    }
+
+    #[test]
+    fn text_without_frequent_segments_is_unchanged() {
+        let segmentation = Segmentation::Lines;
+        let frequent = frequent(segmentation, &["footer"]);
+        let text = "a\r\n\n  b  ";
+        assert_eq!(
+            segmentation.strip(text, &Normalization::default(), &frequent),
+            (text.to_string(), 0)
+        );
+    }
+}
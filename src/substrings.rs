@@ -0,0 +1,486 @@
+use crate::{
+    batch_io::{BatchReader, BatchWriter, Output, TextColumns, TextReader},
+    context::{Context, DocId},
+    lsh::LshBucketsParams,
+    operations,
+};
+use anyhow::{anyhow, Result};
+use arrow::compute::filter_record_batch;
+use arrow_array::{BooleanArray, RecordBatch};
+use cityhasher::hash;
+use indicatif::ProgressBar;
+use log::{debug, error, info};
+use speedy::{IsEof, Readable, Writable};
+use std::{
+    cmp::{self, Reverse},
+    collections::{hash_map::Entry, BinaryHeap, HashMap},
+    fs::{create_dir_all, remove_dir_all, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use threadpool::ThreadPool;
+use unicode_segmentation::UnicodeSegmentation;
+use zstd::stream;
+
+// Ends every document in shard tokens, so suffixes never cross documents
+const SEPARATOR: u32 = u32::MAX;
+
+// Byte offsets and ids of lowercase words of the text
+fn text_tokens(text: &str) -> Vec<(usize, u32)> {
+    text.unicode_word_indices()
+        .map(|(offset, word)| {
+            let id = hash::<u32>(word.to_lowercase());
+            (offset, cmp::min(id, SEPARATOR - 1))
+        })
+        .collect()
+}
+
+#[derive(Readable, Writable)]
+struct ShardDocument {
+    doc_id: DocId,
+    // position of the first token of the document in shard tokens
+    start: u32,
+}
+
+#[derive(Readable, Writable, Default)]
+struct ShardMeta {
+    documents: Vec<ShardDocument>,
+}
+
+// Range of document tokens which is removed, the end is exclusive
+#[derive(Readable, Writable)]
+struct RemovedRange {
+    row_index: u32,
+    start: u32,
+    end: u32,
+}
+
+// Suffixes are sorted by hashes of their first min_tokens tokens only, longer common
+// prefixes are not needed to find repeated spans of min_tokens, suffixes shorter than that
+// are not in the array, equal hashes are ordered by position to keep the first occurrence,
+// shard file has hash and position of every suffix, so shards are merged by sequential reads
+fn write_shard(
+    context: &Context,
+    shard_index: u32,
+    tokens: &[u32],
+    meta: &ShardMeta,
+    min_tokens: usize,
+) -> Result<()> {
+    let mut suffixes = Vec::new();
+    let mut bytes = Vec::with_capacity(min_tokens * 4);
+    for (index, document) in meta.documents.iter().enumerate() {
+        let end = match meta.documents.get(index + 1) {
+            Some(next) => next.start as usize - 1,
+            None => tokens.len() - 1,
+        };
+        let start = document.start as usize;
+        if end - start < min_tokens {
+            continue;
+        }
+        for position in start..=(end - min_tokens) {
+            bytes.clear();
+            for token in &tokens[position..position + min_tokens] {
+                bytes.extend_from_slice(&token.to_le_bytes());
+            }
+            suffixes.push((hash::<u64>(&bytes), position as u32));
+        }
+    }
+    suffixes.sort_unstable();
+
+    debug!(
+        "Writing suffix array shard {}, documents: {}, tokens: {}, suffixes: {}",
+        shard_index,
+        meta.documents.len(),
+        tokens.len(),
+        suffixes.len()
+    );
+
+    let mut writer = BufWriter::new(File::create(
+        context.suffix_array_shard_path(shard_index, "sa"),
+    )?);
+    for (prefix_hash, position) in suffixes {
+        writer.write_all(&prefix_hash.to_le_bytes())?;
+        writer.write_all(&position.to_le_bytes())?;
+    }
+    writer.flush()?;
+    let mut meta_file = stream::Encoder::new(
+        BufWriter::new(File::create(
+            context.suffix_array_shard_path(shard_index, "meta"),
+        )?),
+        1,
+    )?;
+    meta.write_to_stream(&mut meta_file)?;
+    meta_file.finish()?.flush()?;
+
+    Ok(())
+}
+
+// Positions in shard tokens are stored in u32
+fn shard_position(position: usize) -> Result<u32> {
+    u32::try_from(position).map_err(|_| {
+        anyhow!("Shard tokens do not fit in u32 positions, decrease --suffix-array-shard-tokens")
+    })
+}
+
+// Tokens of all documents are concatenated and split into shards of about shard_tokens,
+// every shard has its tokens, suffix array and documents on disk, returns number of shards
+pub fn build_suffix_arrays(
+    context: &Context,
+    params: &LshBucketsParams,
+    min_tokens: usize,
+    shard_tokens: usize,
+) -> Result<u32> {
+    info!("Started building suffix arrays");
+
+    let folder = context.suffix_arrays_folder_path();
+    if Path::new(&folder).exists() {
+        remove_dir_all(&folder)?;
+    }
+    create_dir_all(&folder)?;
+
+    let progress_bar = ProgressBar::new(context.input_files().len() as u64);
+    let mut num_shards = 0u32;
+    let mut tokens = Vec::new();
+    let mut meta = ShardMeta::default();
+    for (file_index, file) in context.input_files().iter().enumerate() {
+        let mut reader = TextReader::try_new(file, params.text_columns())?;
+        let mut row_index = 0u32;
+        while reader.has_data_left()? {
+            let text = reader.next()?;
+            let doc_id = DocId {
+                file_index: file_index as u32,
+                row_index,
+            };
+            row_index += 1;
+            let text = match text {
+                Some(text) => text,
+                None => continue,
+            };
+
+            let text_tokens = text_tokens(&text);
+            if text_tokens.len() < min_tokens {
+                continue;
+            }
+            meta.documents.push(ShardDocument {
+                doc_id,
+                start: shard_position(tokens.len())?,
+            });
+            tokens.extend(text_tokens.into_iter().map(|x| x.1));
+            tokens.push(SEPARATOR);
+            shard_position(tokens.len())?;
+
+            if tokens.len() >= shard_tokens {
+                write_shard(context, num_shards, &tokens, &meta, min_tokens)?;
+                num_shards += 1;
+                tokens.clear();
+                meta = ShardMeta::default();
+            }
+        }
+        progress_bar.inc(1);
+    }
+    if !tokens.is_empty() {
+        write_shard(context, num_shards, &tokens, &meta, min_tokens)?;
+        num_shards += 1;
+    }
+
+    info!("Stopped building suffix arrays, shards: {}", num_shards);
+
+    Ok(num_shards)
+}
+
+// Reads suffixes of a shard in sorted order together with hashes of their prefixes
+struct ShardCursor {
+    suffixes: BufReader<File>,
+    meta: ShardMeta,
+}
+
+impl ShardCursor {
+    fn new(context: &Context, shard_index: u32) -> Result<Self> {
+        let decoder = stream::Decoder::new(File::open(
+            context.suffix_array_shard_path(shard_index, "meta"),
+        )?)?;
+        Ok(Self {
+            suffixes: BufReader::new(File::open(
+                context.suffix_array_shard_path(shard_index, "sa"),
+            )?),
+            meta: ShardMeta::read_from_stream_unbuffered(decoder)?,
+        })
+    }
+
+    // Prefix hash of the next suffix, document of the suffix and its position in the document
+    fn next(&mut self) -> Result<Option<(u64, DocId, u32)>> {
+        let mut buffer = [0u8; 12];
+        match self.suffixes.read_exact(&mut buffer) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let prefix_hash = u64::from_le_bytes(buffer[..8].try_into().unwrap());
+        let position = u32::from_le_bytes(buffer[8..].try_into().unwrap());
+
+        let index = self.meta.documents.partition_point(|x| x.start <= position) - 1;
+        let document = &self.meta.documents[index];
+        Ok(Some((
+            prefix_hash,
+            document.doc_id,
+            position - document.start,
+        )))
+    }
+}
+
+// Suffix arrays of all shards are merged, so equal prefix hashes of the whole corpus are adjacent,
+// the first occurrence of every repeated span is kept and all others are removed
+pub fn find_duplicated_ranges(context: &Context, num_shards: u32, min_tokens: usize) -> Result<()> {
+    info!("Started finding duplicated ranges in {} shards", num_shards);
+
+    let mut cursors = Vec::new();
+    let mut heap = BinaryHeap::new();
+    for shard_index in 0..num_shards {
+        let mut cursor = ShardCursor::new(context, shard_index)?;
+        if let Some((prefix, doc_id, position)) = cursor.next()? {
+            heap.push(Reverse((prefix, doc_id, position, cursors.len())));
+        }
+        cursors.push(cursor);
+    }
+
+    let mut writers = HashMap::new();
+    let mut prev: Option<u64> = None;
+    let mut num_ranges = 0u64;
+    while let Some(Reverse((prefix, doc_id, position, cursor_index))) = heap.pop() {
+        if let Some((next_prefix, next_doc_id, next_position)) = cursors[cursor_index].next()? {
+            heap.push(Reverse((
+                next_prefix,
+                next_doc_id,
+                next_position,
+                cursor_index,
+            )));
+        }
+
+        if prev != Some(prefix) {
+            prev = Some(prefix);
+            continue;
+        }
+
+        if let Entry::Vacant(entry) = writers.entry(doc_id.file_index) {
+            entry.insert(stream::Encoder::new(
+                BufWriter::new(File::create(
+                    context.removed_ranges_file_path(doc_id.file_index),
+                )?),
+                1,
+            )?);
+        }
+        let range = RemovedRange {
+            row_index: doc_id.row_index,
+            start: position,
+            end: position + min_tokens as u32,
+        };
+        range.write_to_stream(writers.get_mut(&doc_id.file_index).unwrap())?;
+        num_ranges += 1;
+    }
+
+    for writer in writers.values_mut() {
+        writer.flush()?;
+    }
+
+    info!(
+        "Stopped finding duplicated ranges, removed ranges: {}",
+        num_ranges
+    );
+
+    Ok(())
+}
+
+// Removes token ranges from the text, text between removed tokens is removed too
+fn remove_ranges(text: &str, ranges: &[(u32, u32)]) -> (String, u64) {
+    let tokens = text_tokens(text);
+    let mut removed = vec![false; tokens.len()];
+    for (start, end) in ranges.iter() {
+        let end = cmp::min(*end as usize, tokens.len());
+        for x in &mut removed[cmp::min(*start as usize, end)..end] {
+            *x = true;
+        }
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut num_removed = 0u64;
+    let mut index = 0;
+    while index < tokens.len() {
+        if !removed[index] {
+            index += 1;
+            continue;
+        }
+        let start = index;
+        while index < tokens.len() && removed[index] {
+            index += 1;
+        }
+        num_removed += (index - start) as u64;
+        result.push_str(&text[copied..tokens[start].0]);
+        copied = tokens.get(index).map_or(text.len(), |x| x.0);
+    }
+    result.push_str(&text[copied..]);
+    (result, num_removed)
+}
+
+fn remove_ranges_in_files(
+    context: &Context,
+    file_indices: &[u32],
+    params: &LshBucketsParams,
+    output: &Output,
+    progress_bar: &ProgressBar,
+    removed_tokens: &AtomicU64,
+) -> Result<()> {
+    for file_index in file_indices {
+        let file = context.input_file(*file_index)?;
+
+        let mut ranges: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+        let ranges_file = context.removed_ranges_file_path(*file_index);
+        if Path::new(&ranges_file).exists() {
+            let mut reader = stream::Decoder::new(File::open(ranges_file)?)?;
+            loop {
+                let range = RemovedRange::read_from_stream_unbuffered(&mut reader);
+                if range.as_ref().is_err_and(|e| e.is_eof()) {
+                    break;
+                }
+                let range = range?;
+                ranges
+                    .entry(range.row_index)
+                    .or_default()
+                    .push((range.start, range.end));
+            }
+        }
+
+        let output_file_path = output.file_path(file)?;
+        debug!("Writing {}", output_file_path);
+
+        let text_columns = params.text_columns();
+        let mut reader = BatchReader::try_new(file, text_columns)?;
+        let schema = reader.schema();
+        let column_index = schema.index_of(&text_columns.names()[0])?;
+        let mut writer = BatchWriter::new(&output_file_path, schema.clone())?;
+
+        let mut row_index = 0u32;
+        while let Some((record_batch, texts)) = reader.next()? {
+            let mask: BooleanArray = texts
+                .iter()
+                .map(|x| Some(!text_columns.is_skipped(x)))
+                .collect();
+            let mut changed = Vec::new();
+            for (i, text) in texts.iter().enumerate() {
+                if let (Some(text), Some(ranges)) = (text, ranges.get(&row_index)) {
+                    let (text, num_removed) = remove_ranges(text, ranges);
+                    if num_removed > 0 {
+                        removed_tokens.fetch_add(num_removed, Ordering::Relaxed);
+                        changed.push((i, text));
+                    }
+                }
+                row_index += 1;
+            }
+            let mut columns = record_batch.columns().to_vec();
+            columns[column_index] = TextColumns::replace_texts(&columns[column_index], &changed)?;
+            let record_batch = RecordBatch::try_new(schema.clone(), columns)?;
+            writer.write(&filter_record_batch(&record_batch, &mask)?)?;
+        }
+        output.add_manifest_entry(
+            file,
+            &output_file_path,
+            reader.num_rows(),
+            writer.num_rows(),
+        );
+        writer.close()?;
+
+        progress_bar.inc(1);
+    }
+
+    Ok(())
+}
+
+// Writes all input files with duplicated ranges removed from texts
+pub fn remove_duplicated_ranges(
+    context: &Context,
+    params: &LshBucketsParams,
+    output: &Output,
+    n_workers: usize,
+) -> Result<()> {
+    info!("Started removing duplicated ranges");
+
+    if !Path::new(&context.suffix_arrays_folder_path()).exists() {
+        return Err(anyhow!("Cannot find suffix arrays folder"));
+    }
+
+    let input_files: Vec<u32> = (0..context.input_files().len() as u32).collect();
+    let removed_tokens = Arc::new(AtomicU64::new(0));
+    let progress_bar = Arc::new(ProgressBar::new(input_files.len() as u64));
+    let pool = ThreadPool::new(n_workers);
+
+    let num_files_per_worker = cmp::max(input_files.len() / n_workers, 1);
+    for start in (0..input_files.len()).step_by(num_files_per_worker) {
+        let worker_files = Vec::from(
+            &input_files[start..cmp::min(input_files.len(), start + num_files_per_worker)],
+        );
+        let context = context.clone();
+        let params = params.clone();
+        let output = output.clone();
+        let progress_bar = progress_bar.clone();
+        let removed_tokens = removed_tokens.clone();
+        let worker = move || {
+            let result = remove_ranges_in_files(
+                &context,
+                &worker_files,
+                &params,
+                &output,
+                &progress_bar,
+                &removed_tokens,
+            );
+            if result.is_err() {
+                error!("{}", result.err().unwrap());
+                panic!();
+            }
+        };
+        pool.execute(worker);
+    }
+    operations::join_workers(&pool)?;
+
+    info!(
+        "Stopped removing duplicated ranges, removed tokens: {}",
+        removed_tokens.load(Ordering::Relaxed)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_ranges_are_removed_once() {
+        let (text, num_removed) = remove_ranges("a b c d e f", &[(1, 3), (2, 4)]);
+        assert_eq!(text, "a e f");
+        assert_eq!(num_removed, 3);
+    }
+
+    #[test]
+    fn adjacent_ranges_are_removed_together() {
+        let (text, num_removed) = remove_ranges("a b c d e f", &[(2, 4), (0, 2)]);
+        assert_eq!(text, "e f");
+        assert_eq!(num_removed, 4);
+    }
+
+    #[test]
+    fn text_between_separate_ranges_is_kept() {
+        let (text, num_removed) = remove_ranges("a, b. c; d", &[(0, 1), (2, 3)]);
+        assert_eq!(text, "b. d");
+        assert_eq!(num_removed, 2);
+    }
+
+    #[test]
+    fn ranges_after_last_token_are_ignored() {
+        let (text, num_removed) = remove_ranges("a b c", &[(1, 10), (5, 8)]);
+        assert_eq!(text, "a ");
+        assert_eq!(num_removed, 2);
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::{anyhow, Result};
+use arrow::{
+    compute::cast,
+    datatypes::{DataType, TimeUnit},
+};
+use arrow_array::{Array, Float64Array, Int64Array, RecordBatch};
+use glob::Pattern;
+use serde::Serialize;
+use speedy::{Readable, Writable};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SurvivorPolicyKind {
+    ContentHash,
+    Longest,
+    Shortest,
+    Earliest,
+    Latest,
+    Highest,
+    SourcePriority,
+}
+
+// Defines which document of a duplicates cluster is kept, every document gets
+// a rank and the document with the highest rank survives,
+// ties are resolved by the lowest content hash
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SurvivorPolicy {
+    ContentHash,
+    Longest,
+    Shortest,
+    Earliest { column: String },
+    Latest { column: String },
+    Highest { column: String },
+    SourcePriority { patterns: Vec<String> },
+}
+
+impl SurvivorPolicy {
+    pub fn new(
+        kind: SurvivorPolicyKind,
+        column: Option<String>,
+        patterns: Vec<String>,
+    ) -> Result<Self> {
+        let column = || {
+            column.clone().ok_or(anyhow!(
+                "--survivor-column is required for {:?} policy",
+                kind
+            ))
+        };
+        Ok(match kind {
+            SurvivorPolicyKind::ContentHash => SurvivorPolicy::ContentHash,
+            SurvivorPolicyKind::Longest => SurvivorPolicy::Longest,
+            SurvivorPolicyKind::Shortest => SurvivorPolicy::Shortest,
+            SurvivorPolicyKind::Earliest => SurvivorPolicy::Earliest { column: column()? },
+            SurvivorPolicyKind::Latest => SurvivorPolicy::Latest { column: column()? },
+            SurvivorPolicyKind::Highest => SurvivorPolicy::Highest { column: column()? },
+            SurvivorPolicyKind::SourcePriority => {
+                if patterns.is_empty() {
+                    return Err(anyhow!(
+                        "--source-priority is required for source-priority policy"
+                    ));
+                }
+                for pattern in &patterns {
+                    Pattern::new(pattern)?;
+                }
+                SurvivorPolicy::SourcePriority { patterns }
+            }
+        })
+    }
+
+    pub fn column(&self) -> Option<&String> {
+        match self {
+            SurvivorPolicy::Earliest { column }
+            | SurvivorPolicy::Latest { column }
+            | SurvivorPolicy::Highest { column } => Some(column),
+            _ => None,
+        }
+    }
+
+    // Ranks rows of the record batch, texts are values of the deduplicated column
+    pub fn rank_batch(
+        &self,
+        path: &str,
+        record_batch: &RecordBatch,
+        texts: &[Option<String>],
+    ) -> Result<Vec<u64>> {
+        let ranks = match self {
+            SurvivorPolicy::ContentHash => vec![0; texts.len()],
+            SurvivorPolicy::Longest => texts.iter().map(text_length).collect(),
+            SurvivorPolicy::Shortest => texts.iter().map(|x| u64::MAX - text_length(x)).collect(),
+            SurvivorPolicy::Earliest { column } => read_timestamps(path, record_batch, column)?
+                .iter()
+                .map(|x| x.map_or(0, |x| u64::MAX - ordered_i64(x)))
+                .collect(),
+            SurvivorPolicy::Latest { column } => read_timestamps(path, record_batch, column)?
+                .iter()
+                .map(|x| x.map_or(0, ordered_i64))
+                .collect(),
+            SurvivorPolicy::Highest { column } => read_scores(path, record_batch, column)?
+                .iter()
+                .map(|x| x.filter(|x| !x.is_nan()).map_or(0, ordered_f64))
+                .collect(),
+            SurvivorPolicy::SourcePriority { patterns } => {
+                let rank = source_rank(path, patterns);
+                vec![rank; texts.len()]
+            }
+        };
+        Ok(ranks)
+    }
+}
+
+fn text_length(text: &Option<String>) -> u64 {
+    text.as_ref().map_or(0, |x| x.chars().count() as u64)
+}
+
+fn ordered_i64(value: i64) -> u64 {
+    (value as u64) ^ (1 << 63)
+}
+
+fn ordered_f64(value: f64) -> u64 {
+    let bits = value.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    }
+}
+
+// Paths of input files are canonical absolute paths, so patterns must match them whole,
+// e.g. **/wiki/* instead of wiki/*
+fn source_rank(path: &str, patterns: &[String]) -> u64 {
+    for (index, pattern) in patterns.iter().enumerate() {
+        if Pattern::new(pattern).unwrap().matches(path) {
+            return (patterns.len() - index) as u64;
+        }
+    }
+    0
+}
+
+fn read_timestamps(
+    path: &str,
+    record_batch: &RecordBatch,
+    column: &str,
+) -> Result<Vec<Option<i64>>> {
+    let mut array = record_batch
+        .column_by_name(column)
+        .ok_or(anyhow!("Cannot find column {} in file {}", column, path))?
+        .clone();
+    if matches!(array.data_type(), DataType::Utf8 | DataType::LargeUtf8) {
+        array = cast(&array, &DataType::Timestamp(TimeUnit::Nanosecond, None))?;
+    }
+    let array = cast(&array, &DataType::Int64)?;
+    let array = array
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .ok_or(anyhow!("Cannot downcast column {} to Int64Array", column))?;
+    Ok(array.iter().collect())
+}
+
+fn read_scores(path: &str, record_batch: &RecordBatch, column: &str) -> Result<Vec<Option<f64>>> {
+    let array = record_batch.column_by_name(column).ok_or(anyhow!(
+        "Cannot find column {} in file {}",
+        column,
+        path
+    ))?;
+    let array = cast(array, &DataType::Float64)?;
+    let array = array
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .ok_or(anyhow!("Cannot downcast column {} to Float64Array", column))?;
+    Ok(array.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::{Field, Schema};
+    use arrow_array::StringArray;
+    use std::sync::Arc;
+
+    fn batch(column: Arc<dyn Array>) -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("x", column.data_type().clone(), true)]);
+        RecordBatch::try_new(Arc::new(schema), vec![column]).unwrap()
+    }
+
+    fn ranks(policy: SurvivorPolicy, column: Arc<dyn Array>) -> Vec<u64> {
+        let batch = batch(column);
+        let texts = vec![None; batch.num_rows()];
+        policy
+            .rank_batch("/data/a.parquet", &batch, &texts)
+            .unwrap()
+    }
+
+    #[test]
+    fn earliest_and_latest_order_timestamps() {
+        let column = Arc::new(Int64Array::from(vec![Some(-5), Some(10), None, Some(0)]));
+        let earliest = ranks(
+            SurvivorPolicy::Earliest {
+                column: "x".to_string(),
+            },
+            column.clone(),
+        );
+        assert!(earliest[0] > earliest[3] && earliest[3] > earliest[1]);
+        assert_eq!(earliest[2], 0);
+
+        let latest = ranks(
+            SurvivorPolicy::Latest {
+                column: "x".to_string(),
+            },
+            column,
+        );
+        assert!(latest[1] > latest[3] && latest[3] > latest[0]);
+        assert_eq!(latest[2], 0);
+    }
+
+    #[test]
+    fn string_timestamps_are_parsed() {
+        let column = Arc::new(StringArray::from(vec![
+            "2021-03-01T00:00:00",
+            "1999-12-31T23:59:59",
+        ]));
+        let earliest = ranks(
+            SurvivorPolicy::Earliest {
+                column: "x".to_string(),
+            },
+            column,
+        );
+        assert!(earliest[1] > earliest[0]);
+    }
+
+    #[test]
+    fn highest_ranks_nan_and_null_lowest() {
+        let column = Arc::new(Float64Array::from(vec![
+            Some(1.5),
+            Some(-2.0),
+            Some(f64::NAN),
+            None,
+            Some(0.0),
+            Some(f64::NEG_INFINITY),
+        ]));
+        let highest = ranks(
+            SurvivorPolicy::Highest {
+                column: "x".to_string(),
+            },
+            column,
+        );
+        assert!(highest[0] > highest[4] && highest[4] > highest[1] && highest[1] > highest[5]);
+        assert!(highest[5] > 0);
+        assert_eq!(highest[2], 0);
+        assert_eq!(highest[3], 0);
+    }
+
+    #[test]
+    fn longest_and_shortest_count_chars() {
+        let batch = batch(Arc::new(Int64Array::from(vec![0, 0, 0])));
+        let texts = vec![Some("abcd".to_string()), Some("ééé".to_string()), None];
+        let longest = SurvivorPolicy::Longest
+            .rank_batch("/data/a.parquet", &batch, &texts)
+            .unwrap();
+        assert_eq!(longest, vec![4, 3, 0]);
+        let shortest = SurvivorPolicy::Shortest
+            .rank_batch("/data/a.parquet", &batch, &texts)
+            .unwrap();
+        assert!(shortest[2] > shortest[1] && shortest[1] > shortest[0]);
+    }
+
+    #[test]
+    fn first_matching_source_pattern_has_top_priority() {
+        let patterns = vec!["**/wiki/*".to_string(), "/data/**".to_string()];
+        assert_eq!(source_rank("/data/wiki/a.parquet", &patterns), 2);
+        assert_eq!(source_rank("/data/web/b.parquet", &patterns), 1);
+        assert_eq!(source_rank("/other/c.parquet", &patterns), 0);
+    }
+
+    #[test]
+    fn source_patterns_match_absolute_paths() {
+        let patterns = vec!["wiki/*".to_string()];
+        assert_eq!(source_rank("/data/wiki/a.parquet", &patterns), 0);
+        assert_eq!(source_rank("wiki/a.parquet", &patterns), 1);
+    }
+}
@@ -0,0 +1,127 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use speedy::{Readable, Writable};
+use unicode_segmentation::UnicodeSegmentation;
+
+lazy_static! {
+    static ref TEXT_SPLITTER: Regex = Regex::new("[^А-Яа-яёЁA-Za-z_0-9]+").unwrap();
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum TokenizerKind {
+    Unicode,
+    Legacy,
+}
+
+#[derive(Readable, Writable, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Tokenizer {
+    // Unicode word segmentation (UAX #29), runs of scripts written without
+    // spaces are kept together and split into character bigrams later
+    Unicode,
+    // Only latin and cyrillic letters and digits, other characters are dropped
+    Legacy,
+}
+
+impl Tokenizer {
+    pub fn new(kind: TokenizerKind) -> Self {
+        match kind {
+            TokenizerKind::Unicode => Tokenizer::Unicode,
+            TokenizerKind::Legacy => Tokenizer::Legacy,
+        }
+    }
+
+    // Lowercase words of the text
+    pub fn words(&self, text: &str) -> Vec<String> {
+        let lowercase = text.to_lowercase();
+        match self {
+            Tokenizer::Legacy => TEXT_SPLITTER
+                .split(&lowercase)
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string())
+                .collect(),
+            Tokenizer::Unicode => {
+                let mut result: Vec<String> = Vec::new();
+                let mut spaceless_run = false;
+                for word in lowercase.unicode_words() {
+                    let spaceless = word.chars().all(is_spaceless);
+                    if spaceless && spaceless_run {
+                        result.last_mut().unwrap().push_str(word);
+                    } else {
+                        result.push(word.to_string());
+                    }
+                    spaceless_run = spaceless;
+                }
+                result
+            }
+        }
+    }
+}
+
+// Scripts which are written without spaces between words
+fn is_spaceless(c: char) -> bool {
+    matches!(c as u32,
+        0x0E00..=0x0EFF // Thai, Lao
+        | 0x1000..=0x109F // Myanmar
+        | 0x1780..=0x17FF // Khmer
+        | 0x3040..=0x30FF // Hiragana, Katakana
+        | 0x31F0..=0x31FF // Katakana extensions
+        | 0x3400..=0x4DBF // CJK extension A
+        | 0x4E00..=0x9FFF // CJK unified ideographs
+        | 0xF900..=0xFAFF // CJK compatibility ideographs
+        | 0xFF66..=0xFF9F // Halfwidth katakana
+        | 0x20000..=0x2FA1F // CJK extensions B-F and supplement
+    )
+}
+
+// Splits runs of spaceless scripts into overlapping character bigrams,
+// other words are returned as is
+pub fn split_spaceless_words(words: &[String]) -> Vec<String> {
+    let mut result = Vec::with_capacity(words.len());
+    for word in words {
+        if !word.chars().any(is_spaceless) {
+            result.push(word.clone());
+            continue;
+        }
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() < 2 {
+            result.push(word.clone());
+            continue;
+        }
+        result.extend(chars.windows(2).map(|x| x.iter().collect::<String>()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<String> {
+        split_spaceless_words(&Tokenizer::Unicode.words(text))
+    }
+
+    #[test]
+    fn accented_latin_words_are_kept() {
+        assert_eq!(tokens("Café NAÏVE résumé"), vec!["café", "naïve", "résumé"]);
+        assert_ne!(tokens("résumé"), tokens("rsum"));
+    }
+
+    #[test]
+    fn spaceless_runs_are_split_into_bigrams() {
+        assert_eq!(tokens("北京天安门"), vec!["北京", "京天", "天安", "安门"]);
+        assert_eq!(tokens("สวัสดีครับ").len(), "สวัสดีครับ".chars().count() - 1);
+        assert_eq!(tokens("猫"), vec!["猫"]);
+    }
+
+    #[test]
+    fn mixed_scripts_do_not_collapse() {
+        assert_eq!(
+            tokens("Hello 世界 wörld 123"),
+            vec!["hello", "世界", "wörld", "123"]
+        );
+        assert_ne!(tokens("东京大学"), tokens("北京大学"));
+        assert!(Tokenizer::Legacy.words("東京 北京").is_empty());
+    }
+}
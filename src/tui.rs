@@ -0,0 +1,287 @@
+use crate::{context::Context, diff::DiffCluster, operations};
+use anyhow::Result;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use similar::{ChangeTag, TextDiff};
+use std::{cmp, collections::HashSet, io::stdout};
+
+const HELP: &str =
+    " ↑/↓ cluster  ←/→ duplicate  PgUp/PgDn scroll  s largest of sample  f false positive  q quit";
+
+// Browser of sampled clusters, clusters marked as false positives are saved
+// to the allowlist right away, so later filters keep their documents,
+// largest first sorts only sampled clusters
+struct Browser<'a> {
+    context: &'a Context,
+    clusters: Vec<DiffCluster>,
+    // indices of clusters in the shown order
+    order: Vec<usize>,
+    largest_first: bool,
+    selected: ListState,
+    duplicate: usize,
+    scroll: u16,
+    allowlist: HashSet<String>,
+}
+
+impl<'a> Browser<'a> {
+    fn new(context: &'a Context, clusters: Vec<DiffCluster>) -> Result<Self> {
+        let mut selected = ListState::default();
+        selected.select(Some(0));
+        Ok(Self {
+            context,
+            order: (0..clusters.len()).collect(),
+            clusters,
+            largest_first: false,
+            selected,
+            duplicate: 0,
+            scroll: 0,
+            allowlist: operations::read_allowlist(context)?,
+        })
+    }
+
+    fn cluster(&self) -> &DiffCluster {
+        &self.clusters[self.order[self.selected.selected().unwrap_or(0)]]
+    }
+
+    fn is_marked(&self, cluster: &DiffCluster) -> bool {
+        cluster.members.iter().all(|x| {
+            self.context
+                .document_name(x)
+                .is_ok_and(|x| self.allowlist.contains(&x))
+        })
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected.select(Some(index));
+        self.duplicate = 0;
+        self.scroll = 0;
+    }
+
+    fn move_cluster(&mut self, step: isize) {
+        let index = self.selected.selected().unwrap_or(0) as isize + step;
+        self.select(index.clamp(0, self.order.len() as isize - 1) as usize);
+    }
+
+    fn move_duplicate(&mut self, step: isize) {
+        let index = self.duplicate as isize + step;
+        self.duplicate = index.clamp(0, self.cluster().duplicates.len() as isize - 1) as usize;
+        self.scroll = 0;
+    }
+
+    fn toggle_largest_first(&mut self) {
+        self.largest_first = !self.largest_first;
+        self.order = (0..self.clusters.len()).collect();
+        if self.largest_first {
+            self.order
+                .sort_by_key(|&i| cmp::Reverse(self.clusters[i].members.len()));
+        }
+        self.select(0);
+    }
+
+    fn toggle_mark(&mut self) -> Result<()> {
+        let marked = self.is_marked(self.cluster());
+        let names: Vec<String> = self
+            .cluster()
+            .members
+            .iter()
+            .map(|x| self.context.document_name(x))
+            .collect::<Result<_>>()?;
+        for name in names {
+            if marked {
+                self.allowlist.remove(&name);
+            } else {
+                self.allowlist.insert(name);
+            }
+        }
+        operations::write_allowlist(self.context, &self.allowlist)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .split(frame.size());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(25), Constraint::Percentage(75)])
+            .split(rows[0]);
+
+        let items: Vec<ListItem> = self
+            .order
+            .iter()
+            .map(|&i| {
+                let cluster = &self.clusters[i];
+                let (mark, style) = if self.is_marked(cluster) {
+                    ("x", Style::default().fg(Color::Yellow))
+                } else {
+                    (" ", Style::default())
+                };
+                ListItem::new(format!(
+                    "{} #{} size {}",
+                    mark,
+                    cluster.cluster_id,
+                    cluster.members.len()
+                ))
+                .style(style)
+            })
+            .collect();
+        let title = if self.largest_first {
+            " Clusters, largest of sample "
+        } else {
+            " Clusters "
+        };
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, columns[0], &mut self.selected);
+
+        let cluster = self.cluster();
+        let mut members = vec![Line::from(Span::styled(
+            format!(
+                "survivor  {}:{}",
+                cluster.survivor.path, cluster.survivor.doc_id.row_index
+            ),
+            Style::default().fg(Color::Red),
+        ))];
+        for (i, duplicate) in cluster.duplicates.iter().enumerate() {
+            let style = if i == self.duplicate {
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default().fg(Color::Green)
+            };
+            members.push(Line::from(Span::styled(
+                format!(
+                    "duplicate {}:{}, similarity {:.3}",
+                    duplicate.path, duplicate.doc_id.row_index, duplicate.similarity
+                ),
+                style,
+            )));
+        }
+        let panes = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(cmp::min(members.len() as u16 + 2, 12)),
+                Constraint::Min(1),
+            ])
+            .split(columns[1]);
+        let title = format!(
+            " Cluster {}, size {}{} ",
+            cluster.cluster_id,
+            cluster.members.len(),
+            if self.is_marked(cluster) {
+                ", false positive"
+            } else {
+                ""
+            }
+        );
+        // member list is scrolled to keep the selected duplicate visible
+        let visible = (panes[0].height as usize).saturating_sub(2);
+        let members_scroll = (self.duplicate + 2).saturating_sub(visible);
+        frame.render_widget(
+            Paragraph::new(members)
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .scroll((members_scroll as u16, 0)),
+            panes[0],
+        );
+
+        let duplicate = &cluster.duplicates[self.duplicate];
+        frame.render_widget(
+            Paragraph::new(diff_lines(&cluster.survivor.text, &duplicate.text))
+                .block(Block::default().borders(Borders::ALL).title(" Diff "))
+                .wrap(Wrap { trim: false })
+                .scroll((self.scroll, 0)),
+            panes[1],
+        );
+
+        frame.render_widget(
+            Paragraph::new(HELP).style(Style::default().add_modifier(Modifier::DIM)),
+            rows[1],
+        );
+    }
+}
+
+// Inline diff of the survivor and the duplicate as in diff::print_diff
+fn diff_lines(old: &str, new: &str) -> Vec<Line<'static>> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut lines = Vec::new();
+    for op in diff.ops() {
+        for change in diff.iter_inline_changes(op) {
+            let (sign, style) = match change.tag() {
+                ChangeTag::Delete => ("-", Style::default().fg(Color::Red)),
+                ChangeTag::Insert => ("+", Style::default().fg(Color::Green)),
+                ChangeTag::Equal => (" ", Style::default().add_modifier(Modifier::DIM)),
+            };
+            let mut spans = vec![Span::styled(sign, style.add_modifier(Modifier::BOLD))];
+            for (emphasized, value) in change.iter_strings_lossy() {
+                let value = value.trim_end_matches('\n').to_string();
+                if emphasized {
+                    spans.push(Span::styled(
+                        value,
+                        style.add_modifier(Modifier::UNDERLINED | Modifier::REVERSED),
+                    ));
+                } else {
+                    spans.push(Span::styled(value, style));
+                }
+            }
+            lines.push(Line::from(spans));
+        }
+    }
+    lines
+}
+
+fn run(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    browser: &mut Browser,
+) -> Result<()> {
+    loop {
+        terminal.draw(|frame| browser.draw(frame))?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => browser.move_cluster(1),
+            KeyCode::Up | KeyCode::Char('k') => browser.move_cluster(-1),
+            KeyCode::Home => browser.select(0),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Tab => browser.move_duplicate(1),
+            KeyCode::Left | KeyCode::Char('h') | KeyCode::BackTab => browser.move_duplicate(-1),
+            KeyCode::PageDown => browser.scroll = browser.scroll.saturating_add(10),
+            KeyCode::PageUp => browser.scroll = browser.scroll.saturating_sub(10),
+            KeyCode::Char('s') => browser.toggle_largest_first(),
+            KeyCode::Char('f') => browser.toggle_mark()?,
+            _ => {}
+        }
+    }
+}
+
+pub fn browse_clusters(context: &Context, clusters: Vec<DiffCluster>) -> Result<()> {
+    if clusters.is_empty() {
+        println!("No clusters to browse");
+        return Ok(());
+    }
+    let mut browser = Browser::new(context, clusters)?;
+
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    // terminal is restored even if browsing fails
+    let result = run(&mut terminal, &mut browser);
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
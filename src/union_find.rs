@@ -0,0 +1,111 @@
+use std::{collections::HashMap, hash::Hash};
+
+// Disjoint sets over arbitrary hashable items, used to merge lsh bucket groups
+// into transitive duplicate clusters
+pub struct UnionFind<T> {
+    items: Vec<T>,
+    indices: HashMap<T, usize>,
+    parents: Vec<usize>,
+    ranks: Vec<u8>,
+}
+
+impl<T> Default for UnionFind<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            indices: HashMap::new(),
+            parents: Vec::new(),
+            ranks: Vec::new(),
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> UnionFind<T> {
+    fn index(&mut self, item: &T) -> usize {
+        if let Some(index) = self.indices.get(item) {
+            return *index;
+        }
+        let index = self.items.len();
+        self.items.push(item.clone());
+        self.indices.insert(item.clone(), index);
+        self.parents.push(index);
+        self.ranks.push(0);
+        index
+    }
+
+    fn find(&mut self, mut index: usize) -> usize {
+        let mut root = index;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        while self.parents[index] != root {
+            let next = self.parents[index];
+            self.parents[index] = root;
+            index = next;
+        }
+        root
+    }
+
+    pub fn union(&mut self, a: &T, b: &T) {
+        let a = self.index(a);
+        let b = self.index(b);
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.ranks[a] < self.ranks[b] {
+            self.parents[a] = b;
+        } else {
+            self.parents[b] = a;
+            if self.ranks[a] == self.ranks[b] {
+                self.ranks[a] += 1;
+            }
+        }
+    }
+
+    // Consumes the structure and returns connected components in order of first insertion
+    pub fn into_components(mut self) -> Vec<Vec<T>> {
+        let mut component_indices = HashMap::new();
+        let mut components: Vec<Vec<T>> = Vec::new();
+        for index in 0..self.items.len() {
+            let root = self.find(index);
+            let component = *component_indices.entry(root).or_insert_with(|| {
+                components.push(Vec::new());
+                components.len() - 1
+            });
+            components[component].push(self.items[index].clone());
+        }
+        components
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unions_are_transitive() {
+        let mut union_find = UnionFind::default();
+        union_find.union(&1, &2);
+        union_find.union(&3, &4);
+        union_find.union(&2, &3);
+        union_find.union(&5, &6);
+        union_find.union(&4, &1);
+        assert_eq!(
+            union_find.into_components(),
+            vec![vec![1, 2, 3, 4], vec![5, 6]]
+        );
+    }
+
+    #[test]
+    fn components_keep_order_of_first_insertion() {
+        let mut union_find = UnionFind::default();
+        union_find.union(&"c", &"c");
+        union_find.union(&"b", &"a");
+        union_find.union(&"d", &"a");
+        assert_eq!(
+            union_find.into_components(),
+            vec![vec!["c"], vec!["b", "a", "d"]]
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use crate::minhash::{shingles_jaccard, MinHash};
+use serde::Serialize;
+use speedy::{Readable, Writable};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum VerificationKind {
+    None,
+    Minhash,
+    Exact,
+}
+
+// Check of candidate pairs from lsh buckets before they are merged into clusters,
+// lsh banding gives only probabilistic candidates, so some of them are false positives
+#[derive(Readable, Writable, Serialize, PartialEq, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Verification {
+    None,
+    // Jaccard similarity estimated from full minhash signatures
+    MinHash { threshold: f64 },
+    // Exact Jaccard similarity of shingles sets
+    Exact { threshold: f64 },
+}
+
+pub enum Signature {
+    MinHash(MinHash),
+    Shingles(Vec<u32>),
+}
+
+impl Verification {
+    pub fn new(kind: VerificationKind, threshold: f64) -> Self {
+        match kind {
+            VerificationKind::None => Verification::None,
+            VerificationKind::Minhash => Verification::MinHash { threshold },
+            VerificationKind::Exact => Verification::Exact { threshold },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Verification::None)
+    }
+
+    pub fn verify(&self, a: &Signature, b: &Signature) -> bool {
+        match self {
+            Verification::None => true,
+            Verification::MinHash { threshold } | Verification::Exact { threshold } => {
+                similarity(a, b) >= *threshold
+            }
+        }
+    }
+}
+
+pub fn similarity(a: &Signature, b: &Signature) -> f64 {
+    match (a, b) {
+        (Signature::MinHash(a), Signature::MinHash(b)) => a.jaccard(b),
+        (Signature::Shingles(a), Signature::Shingles(b)) => shingles_jaccard(a, b),
+        _ => panic!("Signatures of different kinds cannot be compared"),
+    }
+}